@@ -0,0 +1,135 @@
+use crate::ast::{Effects, Effect, Expr};
+
+// the range of heights a stack can have at some point in the program, where no upper bound means it's unbounded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Height {
+    lo: usize,
+    hi: Option<usize>,
+    // a bound on how far the height can be above the number of input values, which holds for both stacks because
+    // a pop can't take a stack below zero
+    rel: Option<usize>,
+}
+
+impl Height {
+    fn apply(self, pop: usize, push: usize) -> Height {
+        Height {
+            lo: self.lo.saturating_sub(pop) + push,
+            hi: self.hi.map(|h| h.saturating_sub(pop) + push),
+            rel: self.rel.map(|h| h.saturating_sub(pop) + push),
+        }
+    }
+
+    fn join(self, other: Height) -> Height {
+        Height {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.zip(other.hi).map(|(a, b)| a.max(b)),
+            rel: self.rel.zip(other.rel).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    // anything still moving after a loop iteration is assumed to keep moving forever, so the analysis terminates
+    fn widen(self, next: Height) -> Height {
+        Height {
+            lo: if next.lo < self.lo { 0 } else { self.lo },
+            hi: if next.hi == self.hi { self.hi } else { None },
+            rel: if next.rel == self.rel { self.rel } else { None },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct State {
+    cur: Height,
+    off: Height,
+}
+
+impl State {
+    fn join(self, other: State) -> State {
+        State { cur: self.cur.join(other.cur), off: self.off.join(other.off) }
+    }
+
+    fn widen(self, next: State) -> State {
+        State { cur: self.cur.widen(next.cur), off: self.off.widen(next.off) }
+    }
+
+    fn depth(self) -> Option<usize> {
+        self.cur.rel.zip(self.off.rel).map(|(a, b)| a.max(b))
+    }
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    pub produces_output: bool,
+    // how far above the number of input values either stack can grow, if that's bounded
+    pub max_depth: Option<usize>,
+}
+
+fn run(e: &Effects, mut state: State, depth: &mut Option<usize>) -> State {
+    for effect in e {
+        state = match effect {
+            Effect::Stack(s) => {
+                let cur = state.cur.apply(s.cur_pop, s.cur_push.len());
+                let off = state.off.apply(s.off_pop, s.off_push.len());
+                if s.toggle {
+                    State { cur: off, off: cur }
+                } else {
+                    State { cur, off }
+                }
+            },
+            // loops run any number of times, so their state is everything reachable after some number of iterations
+            Effect::Loop(e) => {
+                let mut acc = state;
+                while acc.cur.hi != Some(0) {
+                    let entry = State { cur: Height { lo: acc.cur.lo.max(1), ..acc.cur }, off: acc.off };
+                    let next = acc.join(run(&e.effects, entry, depth));
+                    if next == acc {
+                        break;
+                    }
+                    acc = acc.widen(next);
+                }
+                acc
+            },
+            Effect::Countdown(_) => state,
+            Effect::Drain(_) => State { cur: Height { lo: 0, ..state.cur }, off: state.off },
+            // everything that moves was already counted on the current stack
+            Effect::Transfer(_) => State {
+                cur: Height { lo: 0, ..state.cur },
+                off: Height {
+                    lo: state.off.lo,
+                    hi: state.off.hi.zip(state.cur.hi).map(|(a, b)| a + b),
+                    rel: state.off.rel.zip(state.cur.rel).map(|(a, b)| a + b),
+                },
+            },
+            // the total is pushed onto an empty off stack, and otherwise kept in its top
+            Effect::Sum(_) => State {
+                cur: Height { lo: 0, ..state.cur },
+                off: Height { lo: state.off.lo, hi: state.off.hi.map(|h| h.max(1)), rel: state.off.rel.map(|h| h.max(1)) },
+            },
+        };
+        *depth = depth.zip(state.depth()).map(|(a, b)| a.max(b));
+    }
+    state
+}
+
+pub fn analyze(e: &Expr) -> Analysis {
+    // the input can be any number of values, and the off stack always starts empty
+    let start = State { cur: Height { lo: 0, hi: None, rel: Some(0) }, off: Height { lo: 0, hi: Some(0), rel: Some(0) } };
+    let mut max_depth = Some(0);
+    let end = run(&e.effects, start, &mut max_depth);
+    Analysis {
+        produces_output: end.cur.hi != Some(0),
+        max_depth,
+    }
+}
+
+pub fn print(analysis: &Analysis) {
+    if analysis.produces_output {
+        println!("output        possible");
+    } else {
+        println!("output        never (the active stack is always empty at the end)");
+    }
+    match analysis.max_depth {
+        Some(d) => println!("stack depth   input + {}", d),
+        None => println!("stack depth   unbounded"),
+    }
+}
@@ -1,4 +1,6 @@
 use num_bigint::{BigInt, ToBigInt};
+use std::fmt;
+use std::io::Write;
 
 #[derive(Debug)]
 pub enum Inst {
@@ -12,24 +14,346 @@ pub enum Inst {
     Exec(Ast),
 }
 
-pub type Ast = Vec<Inst>;
+impl Inst {
+    /// Makes a `(...)` around the given instructions, which can be an [`Ast`] or a `Vec<Inst>`.
+    pub fn push(children: impl Into<Ast>) -> Inst {
+        Inst::Push(children.into())
+    }
+
+    /// Makes a `[...]` around the given instructions.
+    pub fn negate(children: impl Into<Ast>) -> Inst {
+        Inst::Negate(children.into())
+    }
+
+    /// Makes a `{...}` around the given instructions.
+    pub fn loop_(children: impl Into<Ast>) -> Inst {
+        Inst::Loop(children.into())
+    }
+
+    /// Makes a `<...>` around the given instructions.
+    pub fn exec(children: impl Into<Ast>) -> Inst {
+        Inst::Exec(children.into())
+    }
+
+    /// The instructions inside this one, if it's a monad.
+    pub fn body(&self) -> Option<&Ast> {
+        match self {
+            Inst::One | Inst::Size | Inst::Pop | Inst::Toggle => None,
+            Inst::Push(a) | Inst::Negate(a) | Inst::Loop(a) | Inst::Exec(a) => Some(a),
+        }
+    }
+}
+
+// an instruction and the position of its opening bracket in the source
+#[derive(Debug)]
+pub struct Node {
+    pub inst: Inst,
+    pub pos: usize,
+}
+
+// a newtype rather than an alias so that it can be parsed with str::parse
+#[derive(Debug, Default)]
+pub struct Ast(pub Vec<Node>);
+
+impl std::ops::Deref for Ast {
+    type Target = Vec<Node>;
+
+    fn deref(&self) -> &Vec<Node> {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Ast {
+    fn deref_mut(&mut self) -> &mut Vec<Node> {
+        &mut self.0
+    }
+}
+
+impl IntoIterator for Ast {
+    type Item = Node;
+    type IntoIter = std::vec::IntoIter<Node>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Ast {
+    type Item = &'a Node;
+    type IntoIter = std::slice::Iter<'a, Node>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<Node> for Ast {
+    fn from_iter<I: IntoIterator<Item = Node>>(iter: I) -> Ast {
+        Ast(iter.into_iter().collect())
+    }
+}
+
+// a tree built in code rather than parsed has no source for its positions to point into, so they're all 0
+impl From<Inst> for Node {
+    fn from(inst: Inst) -> Node {
+        Node { inst, pos: 0 }
+    }
+}
+
+impl FromIterator<Inst> for Ast {
+    fn from_iter<I: IntoIterator<Item = Inst>>(iter: I) -> Ast {
+        iter.into_iter().map(Node::from).collect()
+    }
+}
+
+impl From<Vec<Inst>> for Ast {
+    fn from(insts: Vec<Inst>) -> Ast {
+        insts.into_iter().collect()
+    }
+}
+
+/// Builds an [`Ast`] from the names of [`Inst`] variants, with a monad's body in square brackets after its name.
+///
+/// Every position is 0, as for any tree that wasn't parsed.
+///
+/// ```
+/// use flakc::ast;
+///
+/// let tree = ast![Push[One, One], Loop[Push[Pop, Negate[One]]]];
+/// assert_eq!(tree.to_string(), "(()()){({}[()])}");
+/// ```
+#[macro_export]
+macro_rules! ast {
+    ($($inst:ident $([$($body:tt)*])?),* $(,)?) => {
+        $crate::ast::Ast(vec![$($crate::ast::Node::from($crate::ast!(@inst $inst $([$($body)*])?))),*])
+    };
+    (@inst $inst:ident) => {
+        $crate::ast::Inst::$inst
+    };
+    (@inst $inst:ident [$($body:tt)*]) => {
+        $crate::ast::Inst::$inst($crate::ast![$($body)*])
+    };
+}
+
+/// Folds over every instruction in a tree in source order, nested ones included, where a monad comes before the
+/// instructions inside it.
+///
+/// ```
+/// let ast: flakc::Ast = "({}[()])".parse().unwrap();
+/// let monads = flakc::ast::fold_ast(&ast, 0, |n, inst| n + inst.body().is_some() as usize);
+/// assert_eq!(monads, 2);
+/// ```
+pub fn fold_ast<T>(ast: &Ast, init: T, mut f: impl FnMut(T, &Inst) -> T) -> T {
+    fn fold<T>(ast: &Ast, init: T, f: &mut impl FnMut(T, &Inst) -> T) -> T {
+        ast.iter().fold(init, |acc, node| {
+            let acc = f(acc, &node.inst);
+            match node.inst.body() {
+                Some(a) => fold(a, acc, f),
+                None => acc,
+            }
+        })
+    }
+    fold(ast, init, &mut f)
+}
+
+/// A walk over a tree that decides for itself what to do at each node.
+///
+/// The default [`Visit::visit_node`] just carries on into the node's body with [`walk_node`], so an implementation
+/// can do its work before or after calling that, or not call it to skip what's inside.
+pub trait Visit {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node);
+    }
+}
+
+/// Visits each node in a tree in order, without going into their bodies unless the visitor does.
+pub fn walk_ast<V: Visit + ?Sized>(v: &mut V, ast: &Ast) {
+    for node in ast {
+        v.visit_node(node);
+    }
+}
+
+/// Visits each node inside a monad, and does nothing for a nilad.
+pub fn walk_node<V: Visit + ?Sized>(v: &mut V, node: &Node) {
+    if let Some(a) = node.inst.body() {
+        walk_ast(v, a);
+    }
+}
 
+/// Parses Brain-Flak source with the default options.
+///
+/// ```
+/// use flakc::ast::{Ast, Inst};
+///
+/// let ast: Ast = "(()()){}".parse().unwrap();
+/// match &ast[..] {
+///     [push, pop] => {
+///         let Inst::Push(inner) = &push.inst else { panic!("expected a push") };
+///         assert!(inner.iter().all(|n| matches!(n.inst, Inst::One)));
+///         assert!(matches!(pop.inst, Inst::Pop));
+///     },
+///     _ => panic!("expected two instructions"),
+/// }
+///
+/// assert!("(()".parse::<Ast>().is_err());
+/// ```
+impl std::str::FromStr for Ast {
+    type Err = Vec<crate::parser::ParseError>;
+
+    fn from_str(s: &str) -> Result<Ast, Self::Err> {
+        let mut parser = crate::parser::Parser::new(s, crate::parser::Options::default());
+        parser.parse().ok_or_else(|| parser.into_diagnostics())
+    }
+}
+
+/// Writes the tree back out as Brain-Flak source, with no comments or whitespace.
+///
+/// An empty `Push`, `Negate`, `Loop` or `Exec` comes out as a nilad, since the parser never produces one.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in self {
+            match &node.inst {
+                Inst::One => write!(f, "()")?,
+                Inst::Size => write!(f, "[]")?,
+                Inst::Pop => write!(f, "{{}}")?,
+                Inst::Toggle => write!(f, "<>")?,
+                Inst::Push(a) => write!(f, "({})", a)?,
+                Inst::Negate(a) => write!(f, "[{}]", a)?,
+                Inst::Loop(a) => write!(f, "{{{}}}", a)?,
+                Inst::Exec(a) => write!(f, "<{}>", a)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// something found during translation that may be a mistake, reported by the parser alongside its own warnings
+#[derive(Debug)]
+pub struct Lint {
+    pub code: &'static str,
+    pub pos: usize,
+    // the opening bracket of the last instruction it covers, which is pos unless it covers several
+    pub last: usize,
+}
 
+fn write_json_inst(b: &mut impl Write, inst: &Inst, pretty: bool, depth: usize) -> std::io::Result<()> {
+    let (name, a) = match inst {
+        Inst::One => return write!(b, "\"One\""),
+        Inst::Size => return write!(b, "\"Size\""),
+        Inst::Pop => return write!(b, "\"Pop\""),
+        Inst::Toggle => return write!(b, "\"Toggle\""),
+        Inst::Push(a) => ("Push", a),
+        Inst::Negate(a) => ("Negate", a),
+        Inst::Loop(a) => ("Loop", a),
+        Inst::Exec(a) => ("Exec", a),
+    };
+    if pretty {
+        write!(b, "{{\n{:2$}\"{}\": ", "", name, (depth+1)*2)?;
+        write_json_ast(b, a, pretty, depth+1)?;
+        write!(b, "\n{:1$}}}", "", depth*2)
+    } else {
+        write!(b, "{{\"{}\":", name)?;
+        write_json_ast(b, a, pretty, depth)?;
+        write!(b, "}}")
+    }
+}
+
+fn write_json_ast(b: &mut impl Write, ast: &Ast, pretty: bool, depth: usize) -> std::io::Result<()> {
+    if ast.is_empty() {
+        return write!(b, "[]");
+    }
+    write!(b, "[")?;
+    for (i, inst) in ast.iter().enumerate() {
+        if i > 0 {
+            write!(b, ",")?;
+        }
+        if pretty {
+            write!(b, "\n{:1$}", "", (depth+1)*2)?;
+        }
+        write_json_inst(b, &inst.inst, pretty, depth+1)?;
+    }
+    if pretty {
+        write!(b, "\n{:1$}", "", depth*2)?;
+    }
+    write!(b, "]")
+}
+
+// same shape as serde's externally tagged enums: nilads are strings, monads are single-key objects
+pub fn write_json(b: &mut impl Write, ast: &Ast, pretty: bool) -> std::io::Result<()> {
+    write_json_ast(b, ast, pretty, 0)?;
+    writeln!(b)
+}
+
+
+/// Something a [`Value`] reads that isn't known until the program runs.
+///
+/// Stacks are read as they were before the effect that holds the value, so pops in the same effect don't affect them.
+/// Reading below the bottom of a stack gives zero.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ValuePart {
+    /// The element `n` places below the top of the current stack, where 0 is the top.
     CurStackElem(usize),
+    /// The element `n` places below the top of the off stack, where 0 is the top.
     OffStackElem(usize),
-    CurStackSize,
-    OffStackSize,
+    /// The height of the current stack minus `n`, or zero if it's lower than that.
+    CurStackSize(usize),
+    /// The height of the off stack minus `n`, or zero if it's lower than that.
+    OffStackSize(usize),
+    /// The total of a loop's results so far, where `i` is the loop's index in the enclosing [`Effects`].
     LoopResult(usize),
+    /// A value computed once before the loop it's used in, which is listed in [`Expr::hoisted`].
+    Hoisted(usize),
+}
+
+impl fmt::Display for ValuePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValuePart::CurStackElem(n) => write!(f, "cur[{}]", n),
+            ValuePart::OffStackElem(n) => write!(f, "off[{}]", n),
+            ValuePart::CurStackSize(0) => write!(f, "len(cur)"),
+            ValuePart::OffStackSize(0) => write!(f, "len(off)"),
+            ValuePart::CurStackSize(n) => write!(f, "len(cur)-{}", n),
+            ValuePart::OffStackSize(n) => write!(f, "len(off)-{}", n),
+            ValuePart::LoopResult(i) => write!(f, "r{}", i),
+            ValuePart::Hoisted(n) => write!(f, "h{}", n),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+/// A linear combination of things read at runtime: `const_val` plus each part times its coefficient.
+///
+/// For example `({}{}())` pushes `1 + cur[0] + cur[1]`, which is a `const_val` of 1 and the parts
+/// `(CurStackElem(0), 1)` and `(CurStackElem(1), 1)`. No part appears twice and no coefficient is zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Value {
     pub const_val: BigInt,
     pub parts: Vec<(ValuePart, isize)>,
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        if self.parts.is_empty() || self.const_val != 0.into() {
+            write!(f, "{}", self.const_val)?;
+            first = false;
+        }
+        for (part, mul) in &self.parts {
+            write!(f, "{}", match (first, *mul < 0) {
+                (true, true) => "-",
+                (true, false) => "",
+                (false, true) => " - ",
+                (false, false) => " + ",
+            })?;
+            if mul.unsigned_abs() != 1 {
+                write!(f, "{}*", mul.unsigned_abs())?;
+            }
+            write!(f, "{}", part)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
 impl Value {
     fn zero() -> Value {
         Value { const_val: 0.to_bigint().unwrap(), parts: Vec::new() }
@@ -71,10 +395,16 @@ impl Value {
     }
 }
 
+/// A batch of straight-line stack operations.
+///
+/// Every pushed value is read before anything changes. Then each stack has its pops applied followed by its pushes,
+/// in order from bottom to top, and finally the stacks are switched if `toggle` is set.
 #[derive(Debug)]
 pub struct StackEffect {
+    /// How many elements to pop from the current stack, stopping early if it empties.
     pub cur_pop: usize,
     pub cur_push: Vec<Value>,
+    /// How many elements to pop from the off stack, stopping early if it empties.
     pub off_pop: usize,
     pub off_push: Vec<Value>,
     pub toggle: bool,
@@ -97,6 +427,35 @@ impl StackEffect {
         }
     }
 
+    // popping the top of the off stack and pushing it straight back leaves every element reading the same, though it
+    // changes the height when the stack was too short and got padded with zeros
+    pub fn restores_off(&self) -> bool {
+        self.off_push.len() == self.off_pop && self.off_push.iter().enumerate().all(|(i, v)| {
+            v.const_val == 0.into() && v.parts == [(ValuePart::OffStackElem(self.off_pop-1-i), 1)]
+        })
+    }
+
+    // a line for each stack it changes, and one more if it toggles
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (name, pop, push) in [("cur", self.cur_pop, &self.cur_push), ("off", self.off_pop, &self.off_push)] {
+            let mut line = Vec::new();
+            if pop > 0 {
+                line.push(format!("pop {}", pop));
+            }
+            if !push.is_empty() {
+                line.push(format!("push {}", push.iter().map(Value::to_string).collect::<Vec<_>>().join(", ")));
+            }
+            if !line.is_empty() {
+                lines.push(format!("{}: {}", name, line.join(", ")));
+            }
+        }
+        if self.toggle {
+            lines.push("toggle".to_string());
+        }
+        lines
+    }
+
     fn stack_elem(&self, t: usize) -> ValuePart {
         if !self.toggle {
             ValuePart::CurStackElem(t)
@@ -105,27 +464,92 @@ impl StackEffect {
         }
     }
 
-    fn stack_size(&self) -> ValuePart {
+    // the height is taken after the pending pops, which can't take it below zero
+    fn stack_size(&self, pop: usize) -> ValuePart {
         if !self.toggle {
-            ValuePart::CurStackSize
+            ValuePart::CurStackSize(pop)
         } else {
-            ValuePart::OffStackSize
+            ValuePart::OffStackSize(pop)
         }
     }
 }
 
+/// A loop whose body only reads the top of the current stack.
+#[derive(Debug)]
+pub struct TopLoop {
+    /// Each iteration adds `result_const + result_top * top` to the loop's result, where `top` is the top of the
+    /// current stack at the start of the iteration.
+    pub result_const: i64,
+    pub result_top: i64,
+}
+
+impl TopLoop {
+    pub fn result(&self) -> Value {
+        let mut result = Value { const_val: self.result_const.into(), parts: Vec::new() };
+        if self.result_top != 0 {
+            result.parts.push((ValuePart::CurStackElem(0), self.result_top as isize));
+        }
+        result
+    }
+}
+
+/// One step of a translated program. Loops run while the top of the current stack is nonzero.
 #[derive(Debug)]
 pub enum Effect {
     Stack(StackEffect),
+    /// A general loop. Its [`Expr::result`] is added to the loop's result on each iteration.
     Loop(Expr),
+    /// Decrements the top until it reaches zero, so the result has a closed form.
+    Countdown(TopLoop),
+    /// Pops until it reaches a zero or the bottom of the stack.
+    Drain(TopLoop),
+    /// Moves values onto the off stack until it reaches a zero or the bottom of the stack, which reverses their order.
+    Transfer(TopLoop),
+    /// Pops until it reaches a zero or the bottom of the stack, adding each value to the top of the off stack. The
+    /// value is added to the loop's result on each iteration.
+    Sum(Value),
 }
 
 pub type Effects = Vec<Effect>;
 
+/// A translated piece of code: the effects it has on the stacks and the value it evaluates to.
+///
+/// This is what `--emit ir` prints.
 #[derive(Debug)]
 pub struct Expr {
     pub effects: Effects,
+    /// The value of the code, read against the stacks as they are before `effects` run.
     pub result: Value,
+    /// For a loop body, values computed once before the loop starts rather than on every iteration, each
+    /// with the number that [`ValuePart::Hoisted`] refers to it by.
+    pub hoisted: Vec<(usize, Value)>,
+}
+
+fn write_ir_effects(b: &mut impl Write, e: &Effects, depth: usize) -> std::io::Result<()> {
+    for (i, effect) in e.iter().enumerate() {
+        write!(b, "{:1$}", "", depth*2)?;
+        match effect {
+            Effect::Stack(s) => writeln!(b, "{}", s.summary().join("; "))?,
+            Effect::Loop(e) => {
+                writeln!(b, "loop r{} += {}", i, e.result)?;
+                for (n, v) in &e.hoisted {
+                    writeln!(b, "{:w$}hoist h{} = {}", "", n, v, w=depth*2+2)?;
+                }
+                write_ir_effects(b, &e.effects, depth+1)?;
+                writeln!(b, "{:1$}end", "", depth*2)?;
+            },
+            Effect::Countdown(l) => writeln!(b, "countdown r{} += {}", i, l.result())?,
+            Effect::Drain(l) => writeln!(b, "drain r{} += {}", i, l.result())?,
+            Effect::Transfer(l) => writeln!(b, "transfer r{} += {}", i, l.result())?,
+            Effect::Sum(v) => writeln!(b, "sum r{} += {}", i, v)?,
+        }
+    }
+    Ok(())
+}
+
+// one line per effect, with loop bodies indented under them
+pub fn write_ir(b: &mut impl Write, e: &Expr) -> std::io::Result<()> {
+    write_ir_effects(b, &e.effects, 0)
 }
 
 fn push_effect(effects: &mut Effects, effect: StackEffect) {
@@ -134,15 +558,30 @@ fn push_effect(effects: &mut Effects, effect: StackEffect) {
     }
 }
 
-fn translate_with_effects(ast: Ast, effects: &mut Effects, cur_effect: &mut StackEffect) -> Value {
+// used is whether anything ends up reading the result, which a bare () or [] is pointless without
+fn translate_with_effects(ast: Ast, effects: &mut Effects, cur_effect: &mut StackEffect, lints: &mut Vec<Lint>, used: bool) -> Value {
     let mut result = Value::zero();
-    for inst in ast {
+    let mut last_toggle = None;
+    for Node { inst, pos } in ast {
+        // two toggles in a row cancel out, and a third starts a new pair
+        last_toggle = match (&inst, last_toggle) {
+            (Inst::Toggle, Some(p)) => {
+                lints.push(Lint { code: "W0004", pos: p, last: pos });
+                None
+            },
+            (Inst::Toggle, None) => Some(pos),
+            _ => None,
+        };
+        if !used && matches!(inst, Inst::One | Inst::Size) {
+            lints.push(Lint { code: "W0005", pos, last: pos });
+        }
         match inst {
             Inst::One => result.add_const(1),
             Inst::Size => {
-                result.add_part(cur_effect.stack_size());
                 let (pop, push) = cur_effect.pop_push();
-                result.add_const(push.len() as isize - *pop as isize);
+                let (pop, pushed) = (*pop, push.len());
+                result.add_part(cur_effect.stack_size(pop));
+                result.add_const(pushed as isize);
             },
             Inst::Pop => {
                 let (pop, push) = cur_effect.pop_push();
@@ -158,34 +597,264 @@ fn translate_with_effects(ast: Ast, effects: &mut Effects, cur_effect: &mut Stac
             },
             Inst::Toggle => cur_effect.toggle = !cur_effect.toggle,
             Inst::Push(a) => {
-                let r = translate_with_effects(a, effects, cur_effect);
+                // (<...>) and (<>) are the usual ways to push a zero on purpose
+                let explicit = a.iter().all(|n| matches!(n.inst, Inst::Exec(_) | Inst::Toggle));
+                let r = translate_with_effects(a, effects, cur_effect, lints, true);
+                if !explicit && r.parts.is_empty() && r.const_val == 0.into() {
+                    lints.push(Lint { code: "W0003", pos, last: pos });
+                }
                 let (_, push) = cur_effect.pop_push();
                 push.push(r.clone());
                 result.add(r);
             },
             Inst::Negate(a) => {
-                let mut r = translate_with_effects(a, effects, cur_effect);
+                let mut r = translate_with_effects(a, effects, cur_effect, lints, used);
                 r.negate();
                 result.add(r);
             },
             Inst::Loop(a) => {
                 let c = std::mem::replace(cur_effect, StackEffect::new());
                 push_effect(effects, c);
-                effects.push(Effect::Loop(translate(a)));
+                effects.push(Effect::Loop(translate_folded(a, lints, used)));
                 result.add_part(ValuePart::LoopResult(effects.len()-1));
             },
             Inst::Exec(a) => {
-                translate_with_effects(a, effects, cur_effect);
+                // <()> is how zero is usually written, so its () isn't a mistake
+                let zero = matches!(&a[..], [Node { inst: Inst::One, .. }]);
+                translate_with_effects(a, effects, cur_effect, lints, zero);
             },
         }
     }
     result
 }
 
-pub fn translate(ast: Ast) -> Expr {
-    let mut e = Vec::new();
+// [[a]] evaluates to the same thing as a with the same effects, so the pair can be dropped before translating
+fn fold_negations(ast: Ast) -> Ast {
+    let mut r = Ast(Vec::with_capacity(ast.len()));
+    for Node { inst, pos } in ast {
+        let inst = match inst {
+            Inst::Negate(a) => {
+                let mut a = fold_negations(a);
+                if let [Node { inst: Inst::Negate(_), .. }] = &a[..] {
+                    let Some(Node { inst: Inst::Negate(inner), .. }) = a.pop() else { unreachable!() };
+                    r.extend(inner);
+                    continue;
+                }
+                Inst::Negate(a)
+            },
+            Inst::Push(a) => Inst::Push(fold_negations(a)),
+            Inst::Loop(a) => Inst::Loop(fold_negations(a)),
+            Inst::Exec(a) => Inst::Exec(fold_negations(a)),
+            nilad => nilad,
+        };
+        r.push(Node { inst, pos });
+    }
+    r
+}
+
+// counts the loops and pushes that aren't inside a loop, which is what sizes the vectors of a translated expression
+fn count_level(ast: &Ast, loops: &mut usize, pushes: &mut usize) {
+    for node in ast {
+        match &node.inst {
+            Inst::Loop(_) => *loops += 1,
+            Inst::Push(a) => {
+                *pushes += 1;
+                count_level(a, loops, pushes);
+            },
+            Inst::Negate(a) | Inst::Exec(a) => count_level(a, loops, pushes),
+            _ => {},
+        }
+    }
+}
+
+fn translate_folded(ast: Ast, lints: &mut Vec<Lint>, used: bool) -> Expr {
+    let (mut loops, mut pushes) = (0, 0);
+    count_level(&ast, &mut loops, &mut pushes);
+    // every loop is an effect of its own and ends the stack effect before it; the pushes are only a hint for the
+    // first stack effect, since toggles and loops split them up
+    let mut e = Vec::with_capacity(2*loops+1);
     let mut ce = StackEffect::new();
-    let r = translate_with_effects(ast, &mut e, &mut ce);
+    ce.cur_push.reserve(pushes);
+    let r = translate_with_effects(ast, &mut e, &mut ce, lints, used);
     push_effect(&mut e, ce);
-    Expr { effects: e, result: r }
+    Expr { effects: e, result: r, hoisted: Vec::new() }
+}
+
+// nothing else can change the off stack, so a loop body that doesn't touch it reads the same values on every iteration
+fn touches_off(effects: &Effects, size: bool) -> bool {
+    effects.iter().any(|effect| match effect {
+        Effect::Stack(s) => s.toggle || if size { s.off_pop > 0 || !s.off_push.is_empty() } else { !s.restores_off() },
+        Effect::Loop(e) => touches_off(&e.effects, size),
+        Effect::Countdown(_) | Effect::Drain(_) => false,
+        Effect::Transfer(_) | Effect::Sum(_) => true,
+    })
+}
+
+struct Hoister {
+    elems: bool,
+    sizes: bool,
+    hoisted: Vec<(usize, Value)>,
+}
+
+impl Hoister {
+    // the invariant parts of a value are summed into a hoisted temp, leaving the rest to be computed in the loop
+    fn value(&mut self, v: &mut Value, count: &mut usize) {
+        let (inv, rest) = v.parts.drain(..).partition(|(p, _)| match p {
+            ValuePart::OffStackElem(_) => self.elems,
+            ValuePart::OffStackSize(_) => self.sizes,
+            _ => false,
+        });
+        v.parts = rest;
+        if inv.is_empty() {
+            return;
+        }
+        let inv = Value { const_val: 0.into(), parts: inv };
+        let n = match self.hoisted.iter().find(|(_, h)| *h == inv) {
+            Some(&(n, _)) => n,
+            None => {
+                self.hoisted.push((*count, inv));
+                *count += 1;
+                *count - 1
+            },
+        };
+        v.add_part(ValuePart::Hoisted(n));
+    }
+
+    fn effects(&mut self, effects: &mut Effects, count: &mut usize) {
+        for effect in effects {
+            match effect {
+                // anything pushed to the off stack here just puts back what was popped
+                Effect::Stack(s) => {
+                    for v in &mut s.cur_push {
+                        self.value(v, count);
+                    }
+                },
+                Effect::Loop(e) => {
+                    self.value(&mut e.result, count);
+                    self.effects(&mut e.effects, count);
+                },
+                Effect::Countdown(_) | Effect::Drain(_) | Effect::Transfer(_) | Effect::Sum(_) => {},
+            }
+        }
+    }
+}
+
+fn hoist_invariants(effects: &mut Effects, count: &mut usize) {
+    for effect in effects {
+        if let Effect::Loop(e) = effect {
+            let mut h = Hoister { elems: !touches_off(&e.effects, false), sizes: !touches_off(&e.effects, true), hoisted: Vec::new() };
+            if h.elems {
+                h.value(&mut e.result, count);
+                h.effects(&mut e.effects, count);
+                e.hoisted = h.hoisted;
+            }
+            hoist_invariants(&mut e.effects, count);
+        }
+    }
+}
+
+fn as_top_loop(e: &Expr) -> Option<Effect> {
+    let [Effect::Stack(s)] = &e.effects[..] else { return None };
+    if s.cur_pop != 1 || s.off_pop != 0 || s.toggle {
+        return None;
+    }
+    let result_top = match &e.result.parts[..] {
+        [] => 0,
+        [(ValuePart::CurStackElem(0), m)] => (*m).try_into().ok()?,
+        _ => return None,
+    };
+    // i64::MIN is left out so that it never has to be written as a C literal
+    let result_const = i64::try_from(&e.result.const_val).ok().filter(|&c| c != i64::MIN)?;
+    let l = TopLoop { result_const, result_top };
+    let decrement = Value { const_val: (-1).into(), parts: vec![(ValuePart::CurStackElem(0), 1)] };
+    let top = Value { const_val: 0.into(), parts: vec![(ValuePart::CurStackElem(0), 1)] };
+    match (&s.cur_push[..], &s.off_push[..]) {
+        ([], []) => Some(Effect::Drain(l)),
+        ([v], []) if *v == decrement => Some(Effect::Countdown(l)),
+        ([], [v]) if *v == top => Some(Effect::Transfer(l)),
+        _ => None,
+    }
+}
+
+// a loop like {({}<>{})<>} that keeps a running total in the top of the off stack
+fn as_sum_loop(e: &Expr) -> Option<Effect> {
+    let [Effect::Stack(s)] = &e.effects[..] else { return None };
+    if s.cur_pop != 1 || !s.cur_push.is_empty() || s.off_pop != 1 || s.toggle {
+        return None;
+    }
+    let [total] = &s.off_push[..] else { return None };
+    let adds = |part| total.parts.contains(&(part, 1));
+    if total.const_val != 0.into() || total.parts.len() != 2 || !adds(ValuePart::CurStackElem(0)) || !adds(ValuePart::OffStackElem(0)) {
+        return None;
+    }
+    let reads_tops = e.result.parts.iter().all(|(p, _)| matches!(p, ValuePart::CurStackElem(0) | ValuePart::OffStackElem(0)));
+    reads_tops.then(|| Effect::Sum(e.result.clone()))
+}
+
+fn fold_top_loops(effects: &mut Effects) {
+    for effect in effects {
+        if let Effect::Loop(e) = effect {
+            if let Some(l) = as_top_loop(e).or_else(|| as_sum_loop(e)) {
+                *effect = l;
+            } else {
+                fold_top_loops(&mut e.effects);
+            }
+        }
+    }
+}
+
+/// An optimization that [`translate_with`] can run, named on the command line by `-fno-<pass>` and `--opt-passes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Drops pairs of nested negations like `[[a]]`. This works on the tree, so it always runs before the others.
+    FoldNegations,
+    /// Replaces loops that follow a known pattern, like counting down or moving a stack, with [`Effect::Countdown`],
+    /// [`Effect::Drain`], [`Effect::Transfer`] or [`Effect::Sum`].
+    LoopIdioms,
+    /// Moves values that can't change while a loop runs into [`Expr::hoisted`].
+    Hoist,
+}
+
+impl Pass {
+    /// Every pass, in the order [`translate`] runs them.
+    pub const ALL: [Pass; 3] = [Pass::FoldNegations, Pass::LoopIdioms, Pass::Hoist];
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Pass::FoldNegations => "fold-negations",
+            Pass::LoopIdioms => "loop-idioms",
+            Pass::Hoist => "hoist",
+        })
+    }
+}
+
+impl std::str::FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Pass, String> {
+        Pass::ALL.into_iter().find(|p| p.to_string() == s)
+            .ok_or_else(|| format!("unknown pass `{}` (expected fold-negations, loop-idioms or hoist)", s))
+    }
+}
+
+pub fn translate(ast: Ast, lints: &mut Vec<Lint>) -> Expr {
+    translate_with(ast, lints, &Pass::ALL)
+}
+
+/// Like [`translate`], but only runs the given passes, in the given order.
+///
+/// Hoisting more than once would lose the values hoisted the first time, so each pass should appear at most once.
+pub fn translate_with(ast: Ast, lints: &mut Vec<Lint>, passes: &[Pass]) -> Expr {
+    let ast = if passes.contains(&Pass::FoldNegations) { fold_negations(ast) } else { ast };
+    let mut e = translate_folded(ast, lints, false);
+    for pass in passes {
+        match pass {
+            Pass::FoldNegations => {},
+            Pass::LoopIdioms => fold_top_loops(&mut e.effects),
+            Pass::Hoist => hoist_invariants(&mut e.effects, &mut 0),
+        }
+    }
+    e
 }
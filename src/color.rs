@@ -0,0 +1,40 @@
+// without the color feature, diagnostics use these stand-ins for colored's methods, which leave the text plain
+#[cfg(feature = "color")]
+pub use colored::Colorize;
+
+#[cfg(not(feature = "color"))]
+pub trait Colorize: std::fmt::Display {
+    fn red(&self) -> String {
+        self.to_string()
+    }
+
+    fn green(&self) -> String {
+        self.to_string()
+    }
+
+    fn blue(&self) -> String {
+        self.to_string()
+    }
+
+    fn bold(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[cfg(not(feature = "color"))]
+impl Colorize for str {}
+
+#[cfg(not(feature = "color"))]
+impl Colorize for String {}
+
+// whether diagnostics will actually be colored, which colored decides from NO_COLOR, CLICOLOR and whether stdout is
+// a terminal
+#[cfg(feature = "color")]
+pub fn enabled() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
+#[cfg(not(feature = "color"))]
+pub fn enabled() -> bool {
+    false
+}
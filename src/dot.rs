@@ -0,0 +1,69 @@
+use crate::ast::{Effects, Effect, TopLoop, Expr};
+use std::io::Write;
+
+fn top_loop_label(kind: &str, i: usize, l: &TopLoop) -> String {
+    format!("{} r{}\\nr{} += {}", kind, i, i, l.result())
+}
+
+fn node(b: &mut impl Write, id: usize, shape: &str, label: &str) -> std::io::Result<()> {
+    writeln!(b, "  n{} [shape={}, label=\"{}\"];", id, shape, label.replace('"', "\\\""))
+}
+
+// returns the node that control leaves from, which for a loop is its condition
+fn write_effects(b: &mut impl Write, e: Effects, count: &mut usize, mut prev: usize) -> std::io::Result<usize> {
+    for (i, effect) in e.into_iter().enumerate() {
+        let id = *count;
+        *count += 1;
+        match effect {
+            Effect::Stack(s) => {
+                node(b, id, "box", &s.summary().join("\\n"))?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+            },
+            Effect::Loop(Expr { effects, result, hoisted }) => {
+                writeln!(b, "  subgraph cluster_{} {{", id)?;
+                writeln!(b, "  label=\"loop r{}\";", i)?;
+                let mut label = format!("top != 0\\nr{} += {}", i, result);
+                for (n, v) in &hoisted {
+                    label.push_str(&format!("\\nh{} = {}", n, v));
+                }
+                node(b, id, "diamond", &label)?;
+                let last = write_effects(b, effects, count, id)?;
+                writeln!(b, "  n{} -> n{} [style=dashed];", last, id)?;
+                writeln!(b, "  }}")?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+            },
+            Effect::Countdown(l) => {
+                node(b, id, "diamond", &top_loop_label("countdown", i, &l))?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+                writeln!(b, "  n{} -> n{} [style=dashed];", id, id)?;
+            },
+            Effect::Drain(l) => {
+                node(b, id, "diamond", &top_loop_label("drain", i, &l))?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+                writeln!(b, "  n{} -> n{} [style=dashed];", id, id)?;
+            },
+            Effect::Transfer(l) => {
+                node(b, id, "diamond", &top_loop_label("transfer", i, &l))?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+                writeln!(b, "  n{} -> n{} [style=dashed];", id, id)?;
+            },
+            Effect::Sum(v) => {
+                node(b, id, "diamond", &format!("sum r{}\\nr{} += {}", i, i, v))?;
+                writeln!(b, "  n{} -> n{};", prev, id)?;
+                writeln!(b, "  n{} -> n{} [style=dashed];", id, id)?;
+            },
+        }
+        prev = id;
+    }
+    Ok(prev)
+}
+
+pub fn write_dot(b: &mut impl Write, e: Expr) -> std::io::Result<()> {
+    writeln!(b, "digraph flakc {{")?;
+    writeln!(b, "  n0 [shape=circle, label=\"start\"];")?;
+    let mut count = 1;
+    let last = write_effects(b, e.effects, &mut count, 0)?;
+    writeln!(b, "  n{} [shape=doublecircle, label=\"end\"];", count)?;
+    writeln!(b, "  n{} -> n{};", last, count)?;
+    writeln!(b, "}}")
+}
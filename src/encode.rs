@@ -0,0 +1,83 @@
+use crate::ast::{Ast, Node, Inst};
+use num_bigint::{BigInt, BigUint, Sign};
+use std::collections::HashMap;
+
+// how a positive number is built: k*factor + offset for some smaller k, or offset ones on their own
+#[derive(Clone, Copy)]
+struct Step {
+    factor: u32,
+    offset: i32,
+}
+
+fn ones(n: usize) -> impl Iterator<Item=Node> {
+    (0..n).map(|_| Node::from(Inst::One))
+}
+
+// finds the cheapest way to write each number that n is built from, measured in characters of source
+fn plan(n: &BigUint, memo: &mut HashMap<BigUint, (usize, Step)>) -> usize {
+    if let Some(&(cost, _)) = memo.get(n) {
+        return cost;
+    }
+    let mut best = match u32::try_from(n) {
+        Ok(small) if small <= 16 => (2*small as usize, Step { factor: 1, offset: small as i32 }),
+        _ => (usize::MAX, Step { factor: 1, offset: 0 }),
+    };
+    for factor in [2u32, 3] {
+        let k = n / factor;
+        let r = (n % factor).to_u32_digits().first().copied().unwrap_or(0);
+        // multiplying by f takes f-1 nested pushes and as many pops to add the copies back up
+        let wrap = 4*(factor as usize - 1);
+        if k > BigUint::from(1u32) {
+            let cost = plan(&k, memo) + wrap + 2*r as usize;
+            if cost < best.0 {
+                best = (cost, Step { factor, offset: r as i32 });
+            }
+        }
+        // rounding up and subtracting the difference is shorter when n is just below a multiple
+        if r != 0 && &k + 1u32 < *n {
+            let cost = plan(&(k + 1u32), memo) + wrap + 2 + 2*(factor - r) as usize;
+            if cost < best.0 {
+                best = (cost, Step { factor, offset: r as i32 - factor as i32 });
+            }
+        }
+    }
+    memo.insert(n.clone(), best);
+    best.0
+}
+
+fn build(n: &BigUint, memo: &HashMap<BigUint, (usize, Step)>) -> Ast {
+    let Step { factor, offset } = memo[n].1;
+    if factor == 1 {
+        return ones(offset as usize).collect();
+    }
+    let k = if offset < 0 { n / factor + 1u32 } else { n / factor };
+    let mut inner = build(&k, memo);
+    for _ in 1..factor {
+        inner = Ast::from(vec![Inst::push(inner)]);
+    }
+    let mut a = inner;
+    a.extend((1..factor).map(|_| Node::from(Inst::Pop)));
+    if offset < 0 {
+        a.push(Node::from(Inst::negate(ones(-offset as usize).collect::<Ast>())));
+    } else {
+        a.extend(ones(offset as usize));
+    }
+    a
+}
+
+/// Builds a program that pushes `n` onto the active stack and leaves everything else as it was.
+///
+/// Rather than writing `n` in unary, the value is built by repeatedly doubling or tripling a smaller one, so the
+/// program grows with the number of digits in `n` instead of its size.
+pub fn encode(n: &BigInt) -> Ast {
+    let (sign, magnitude) = (n.sign(), n.magnitude());
+    if sign == Sign::NoSign {
+        // () would push a one, so zero has to be the value of an instruction that discards its contents
+        return crate::ast![Push[Exec[One]]];
+    }
+    let mut memo = HashMap::new();
+    plan(magnitude, &mut memo);
+    let value = build(magnitude, &memo);
+    let value = if sign == Sign::Minus { Ast::from(vec![Inst::negate(value)]) } else { value };
+    Ast::from(vec![Inst::push(value)])
+}
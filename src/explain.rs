@@ -0,0 +1,105 @@
+pub const EXPLANATIONS: &[(&str, &str)] = &[
+    ("W0001", "\
+Instructions appear after junk characters on the same line.
+
+Any character that isn't a bracket is ignored, so prose can be written
+anywhere in a program. This warning fires when a line contains prose and
+then brackets after it, for example:
+
+    this pushes one (())
+
+It's common for comments written this way to accidentally contain
+brackets, which then silently become part of the program. Either move
+the prose onto its own line after the code, start it with # to make it
+a line comment, or wrap it in a #{block comment} if the code after it
+is intended:
+
+    # this pushes one
+    (())
+"),
+    ("W0002", "\
+Junk characters are enclosed within a nilad.
+
+Something like ( ) or (a) is still treated as the nilad (), because the
+characters between the brackets are ignored. This is legal, but it makes
+it hard to see at a glance that the brackets form a nilad rather than
+the start of a monad. Remove the characters between the brackets:
+
+    ( )  ->  ()
+"),
+    ("W0003", "\
+A push always pushes zero.
+
+A push whose value works out to 0 no matter what the stacks hold, like
+(()[()]), grows the stack by a zero. That's sometimes intended, but more
+often a sign that the value being pushed isn't what the author thought
+it was. The usual ways to push a zero on purpose are (<...>) and (<>),
+which don't trigger this warning:
+
+    (()[()])  ->  (<()>)
+"),
+    ("W0004", "\
+Two toggles in a row cancel each other out.
+
+<> switches the active stack, so <><> switches to the other stack and
+straight back again. The pair has no effect and can be removed:
+
+    (())<><>{}  ->  (()){}
+"),
+    ("W0005", "\
+A value is computed and then discarded.
+
+() and [] have no effect other than producing a value, so they do
+nothing when that value isn't used. This happens at the top level of a
+program, inside <...>, and inside a loop or [...] whose own value is
+discarded. For example, the () here is added to nothing:
+
+    (())()  ->  (())
+
+Use <()> when a zero is wanted; its () is not reported.
+"),
+    ("E0001", "\
+A block comment is never closed.
+
+Block comments start with #{ and end when the braces inside them are
+balanced again, or at an explicit }# while they're still unbalanced. If the end
+of the file is reached while still inside a block comment, the rest of
+the program would be silently commented out, so this is an error. When a brace
+inside the comment is what's left open, the error points at that brace
+and a second label shows where the comment itself started. Add the missing } or }#.
+"),
+    ("E0002", "\
+A delimiter is opened but never closed.
+
+Every (, [, { and < must be matched by a closing ), ], } or > before the
+end of the program. Add the missing closing delimiter.
+"),
+    ("E0003", "\
+A delimiter is closed by the wrong kind of bracket.
+
+For example, in ((}) the inner ( is followed by }, which can only close
+a {. Brain-Flak has no way to interpret mismatched brackets, so the
+closing bracket has to be changed to match its opener.
+"),
+    ("E0004", "\
+A closing delimiter has no matching opener.
+
+The program contains a ), ], } or > that doesn't close anything, for
+example the final ) in (())). Remove it, or add the opener it was meant
+to close.
+"),
+    ("E0005", "\
+A junk character appears outside of a comment in strict mode.
+
+Normally any character that isn't a bracket is ignored. With --strict,
+only brackets, whitespace and comments are allowed, so that stray
+characters can't hide accidental instructions. Move the text into a
+comment:
+
+    push one (())  ->  (())  # push one
+"),
+];
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS.iter().find(|(c, _)| c.eq_ignore_ascii_case(code)).map(|(_, e)| *e)
+}
@@ -1,89 +1,1014 @@
-use crate::ast::{Value, ValuePart, Effects, Effect, StackEffect, Expr};
+use crate::ast::{Value, ValuePart, Effects, Effect, StackEffect, Expr, TopLoop, Pass};
+use crate::analyze;
 use std::io::Write;
 
-fn compile_value(b: &mut impl Write, v: Value) -> std::io::Result<()> {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Args,
+    StdinNumbers,
+    StdinChars,
+    Line,
+    Baked,
+}
+
+// what --stdin-chars pushes after the last character, for programs that look for a sentinel to find the end of input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eof {
+    None,
+    Zero,
+    NegOne,
+}
+
+impl Eof {
+    pub fn sentinel(self) -> Option<i64> {
+        match self {
+            Eof::None => None,
+            Eof::Zero => Some(0),
+            Eof::NegOne => Some(-1),
+        }
+    }
+}
+
+impl std::str::FromStr for Eof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Eof, String> {
+        match s {
+            "none" => Ok(Eof::None),
+            "zero" => Ok(Eof::Zero),
+            "neg1" => Ok(Eof::NegOne),
+            _ => Err(format!("unknown EOF value `{}` (expected none, zero or neg1)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Stack,
+    Top,
+    Json,
+    // nothing at all, for timing a program without its output
+    None,
+}
+
+// how printed values are written in hexadecimal, with a 0x in front of each if prefix is set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hex {
+    pub prefix: bool,
+    pub negatives: HexNegatives,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HexNegatives {
+    // a minus sign and then the magnitude, as in decimal
+    Sign,
+    // the bits of the 64-bit value, so -1 is ffffffffffffffff
+    TwosComplement,
+}
+
+impl std::str::FromStr for HexNegatives {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HexNegatives, String> {
+        match s {
+            "sign" => Ok(HexNegatives::Sign),
+            "twos-complement" => Ok(HexNegatives::TwosComplement),
+            _ => Err(format!("unknown way to write negative values `{}` (expected sign or twos-complement)", s)),
+        }
+    }
+}
+
+// whether a newline is printed after the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingNewline {
+    Never,
+    // only if something was printed, so an empty stack prints nothing at all
+    IfNotEmpty,
+    Always,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntType {
+    I64,
+    U64,
+}
+
+// everything the generated code needs to know about the type of a value, so that adding a type only touches this;
+// C89 has no long long, so there a value is a long, which is only 64 bits wide where the platform's long is
+impl IntType {
+    // unsigned values wrap modulo 2^64, so negative coefficients and constants still behave as subtraction
+    fn c_type(self, std: CStandard) -> &'static str {
+        match (self, std) {
+            (IntType::I64, CStandard::C99) => "long long",
+            (IntType::U64, CStandard::C99) => "unsigned long long",
+            (IntType::I64, CStandard::C89) => "long",
+            (IntType::U64, CStandard::C89) => "unsigned long",
+        }
+    }
+
+    // the function num uses to parse a value from text, which sets errno to ERANGE when it doesn't fit
+    fn parse_fn(self, std: CStandard) -> &'static str {
+        match (self, std) {
+            (IntType::I64, CStandard::C99) => "strtoll",
+            (IntType::U64, CStandard::C99) => "strtoull",
+            (IntType::I64, CStandard::C89) => "strtol",
+            (IntType::U64, CStandard::C89) => "strtoul",
+        }
+    }
+
+    // a C literal for the value written in s, if it's a valid one
+    fn literal(self, s: &str, std: CStandard) -> Option<String> {
+        let suffix = match std {
+            CStandard::C99 => "LL",
+            CStandard::C89 => "L",
+        };
+        match self {
+            // the most negative value can't be written directly, since the minus is applied to a literal that's too big
+            IntType::I64 => s.parse::<i64>().ok().map(|x| if x == i64::MIN { format!("({}{}-1)", x+1, suffix) } else { format!("{}{}", x, suffix) }),
+            IntType::U64 => s.parse::<u64>().ok().map(|x| format!("{}U{}", x, suffix)),
+        }
+    }
+
+    // the printf conversion for a value
+    fn format(self, std: CStandard) -> &'static str {
+        match (self, std) {
+            (IntType::I64, CStandard::C99) => "%lld",
+            (IntType::U64, CStandard::C99) => "%llu",
+            (IntType::I64, CStandard::C89) => "%ld",
+            (IntType::U64, CStandard::C89) => "%lu",
+        }
+    }
+
+    // code that turns the value x into its magnitude u as an unsigned value, and code that writes a minus sign through
+    // q if it needs one, for printing the digits by hand
+    fn magnitude(self, std: CStandard) -> (String, &'static str) {
+        let u = std.unsigned();
+        match self {
+            IntType::I64 => (format!("{u} u=x<0?-({u})x:({u})x;", u=u), "if(x<0)*q++=45;"),
+            IntType::U64 => (format!("{} u=x;", u), ""),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arithmetic {
+    Wrapping,
+    Saturating,
+    Mod256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CStandard {
+    C89,
+    C99,
+}
+
+impl std::str::FromStr for CStandard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CStandard, String> {
+        match s {
+            "c89" => Ok(CStandard::C89),
+            "c99" => Ok(CStandard::C99),
+            _ => Err(format!("unknown C standard `{}` (expected c89 or c99)", s)),
+        }
+    }
+}
+
+impl CStandard {
+    // the widest unsigned type there is, which counters and the magnitudes of values are kept in
+    fn unsigned(self) -> &'static str {
+        match self {
+            CStandard::C89 => "unsigned long",
+            CStandard::C99 => "unsigned long long",
+        }
+    }
+
+    // the suffix of a literal of that type
+    fn unsigned_suffix(self) -> &'static str {
+        match self {
+            CStandard::C89 => "UL",
+            CStandard::C99 => "ULL",
+        }
+    }
+
+    // the smallest and largest values of the widest signed type
+    fn limits(self) -> (&'static str, &'static str) {
+        match self {
+            CStandard::C89 => ("LONG_MIN", "LONG_MAX"),
+            CStandard::C99 => ("LLONG_MIN", "LLONG_MAX"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub input: Input,
+    // the initial stack for Input::Baked
+    pub baked: Vec<String>,
+    pub eof: Eof,
+    pub output: Output,
+    pub separator: String,
+    pub trailing_newline: TrailingNewline,
+    // print values in hexadecimal rather than decimal
+    pub hex: Option<Hex>,
+    pub loop_limit: Option<u64>,
+    pub c_standard: CStandard,
+    pub int_type: IntType,
+    pub arithmetic: Arithmetic,
+    pub flush: bool,
+    // check every access to a stack that the effects make, aborting with a message instead of going out of bounds, and
+    // assert what the optimizations assume, which -DNDEBUG turns off again
+    pub debug_checks: bool,
+    // allocate the stacks with calloc, so that reading past the top of one gives zeros rather than whatever was there
+    pub zero_init: bool,
+    // how many values the off stack has room for before it first has to grow, which the active stack starts with 1024
+    pub off_capacity: usize,
+    // print the top of the active stack when the program is interrupted with Ctrl-C, which needs the stacks to be
+    // reachable from file scope and so only works with a main
+    pub dump_on_interrupt: bool,
+    // compile to a function with this name that takes the initial stack and returns the final one, instead of a main
+    pub function: Option<String>,
+    // the optimizations run while translating, which happens before this module sees the program but changes what
+    // it's given to compile
+    pub passes: Vec<Pass>,
+    // filled in from the static analysis when compiling rather than set by the caller
+    pub max_depth: Option<usize>,
+}
+
+impl Default for Options {
+    // the same settings flakc uses when given no options
+    fn default() -> Options {
+        Options {
+            input: Input::Args,
+            baked: Vec::new(),
+            eof: Eof::None,
+            output: Output::Stack,
+            separator: String::from("\n"),
+            trailing_newline: TrailingNewline::IfNotEmpty,
+            hex: None,
+            loop_limit: None,
+            c_standard: CStandard::C99,
+            int_type: IntType::I64,
+            arithmetic: Arithmetic::Wrapping,
+            flush: false,
+            debug_checks: false,
+            zero_init: false,
+            off_capacity: 1024,
+            dump_on_interrupt: false,
+            function: None,
+            passes: Pass::ALL.to_vec(),
+            max_depth: None,
+        }
+    }
+}
+
+fn c_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.bytes() {
+        match c {
+            b'"' | b'\\' => { r.push('\\'); r.push(c as char); },
+            b'\n' => r.push_str("\\n"),
+            b' '..=b'~' => r.push(c as char),
+            _ => r.push_str(&format!("\\{:03o}", c)),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// an element of a stack, which with debug checks is found by a function that aborts unless the index is below the
+// stack's size, or its capacity if the element is past the top where it's being pushed
+fn elem(opts: &Options, stack: &str, index: &str, past_top: bool) -> String {
+    if !opts.debug_checks {
+        return format!("{}[{}]", stack, index);
+    }
+    let (size, capacity, name) = if stack == "s" { ("p", "c", "active") } else { ("d", "v", "off") };
+    if past_top {
+        format!("*ck({},{},{},\"write past the capacity of the {} stack\")", stack, index, capacity, name)
+    } else {
+        format!("*ck({},{},{},\"access past the top of the {} stack\")", stack, index, size, name)
+    }
+}
+
+fn compile_part(b: &mut impl Write, part: ValuePart, opts: &Options) -> std::io::Result<()> {
+    match part {
+        ValuePart::CurStackElem(n) => write!(b, "(p>{}?{}:0)", n, elem(opts, "s", &format!("p-{}", n+1), false)),
+        ValuePart::OffStackElem(n) => write!(b, "(d>{}?{}:0)", n, elem(opts, "o", &format!("d-{}", n+1), false)),
+        ValuePart::CurStackSize(0) => write!(b, "p"),
+        ValuePart::OffStackSize(0) => write!(b, "d"),
+        ValuePart::CurStackSize(n) => write!(b, "(p>{}?p-{}:0)", n, n),
+        ValuePart::OffStackSize(n) => write!(b, "(d>{}?d-{}:0)", n, n),
+        ValuePart::LoopResult(i) => write!(b, "r{}", i),
+        ValuePart::Hoisted(n) => write!(b, "h{}", n),
+    }
+}
+
+fn compile_value(b: &mut impl Write, v: Value, opts: &Options) -> std::io::Result<()> {
+    if opts.arithmetic == Arithmetic::Saturating {
+        // constants outside the range of l are clamped too, so they can't wrap before the helpers see them
+        let c = v.const_val.clamp(i64::MIN.into(), i64::MAX.into());
+        write!(b, "{}", "sa(".repeat(v.parts.len()))?;
+        if c == i64::MIN.into() {
+            write!(b, "{}", opts.c_standard.limits().0)?;
+        } else {
+            write!(b, "{}", c)?;
+        }
+        for (part, mul) in v.parts {
+            write!(b, ",")?;
+            if mul != 1 {
+                write!(b, "sm(")?;
+            }
+            compile_part(b, part, opts)?;
+            if mul != 1 {
+                write!(b, ",{})", mul)?;
+            }
+            write!(b, ")")?;
+        }
+        return Ok(());
+    }
     write!(b, "({}", v.const_val)?;
     for (part, mul) in v.parts {
-        write!(b, "+")?;
-        match part {
-            ValuePart::CurStackElem(n) => write!(b, "s[p-{}]", n+1)?,
-            ValuePart::OffStackElem(n) => write!(b, "o[d-{}]", n+1)?,
-            ValuePart::CurStackSize => write!(b, "p")?,
-            ValuePart::OffStackSize => write!(b, "d")?,
-            ValuePart::LoopResult(i) => write!(b, "r{}", i)?,
-        };
-        if mul != 1 {
-            write!(b, "*{}", mul)?;
+        // negated parts are written as subtractions rather than multiplications by -1
+        write!(b, "{}", if mul < 0 { "-" } else { "+" })?;
+        compile_part(b, part, opts)?;
+        if mul.unsigned_abs() != 1 {
+            write!(b, "*{}", mul.unsigned_abs())?;
         }
     }
     write!(b, ")")?;
     Ok(())
 }
 
-fn compile_single_stack_effect(b: &mut impl Write, pop: usize, push: Vec<Value>, is_off: bool, effect_index: usize) -> std::io::Result<isize> {
-    let (stack, top, cap) = if !is_off {
-        ("s", "p", "c")
+// C89 doesn't allow declarations after statements, so there they're collected to be written at the start of the block
+fn declare(b: &mut impl Write, decls: &mut Vec<String>, opts: &Options, ty: &str, name: String) -> std::io::Result<()> {
+    if opts.c_standard == CStandard::C89 {
+        write!(b, "{}", name)?;
+        decls.push(format!("{} {}", ty, name));
+        Ok(())
     } else {
-        ("o", "d", "v")
-    };
-    let offset = push.len() as isize - pop as isize;
-    if offset > 0 {
-        write!(b, "if({p}+{}>{c}){{{c}*=2;{s}=realloc({s},{c}*sizeof(l));}}", offset, s=stack, p=top, c=cap)?;
-    }
-    let l = push.len();
-    for (i, elem) in push.into_iter().enumerate() {
-        write!(b, "l t{}_{}=", i, effect_index)?;
-        compile_value(b, elem)?;
+        write!(b, "{} {}", ty, name)
+    }
+}
+
+fn write_decls(b: &mut impl Write, decls: &[String]) -> std::io::Result<()> {
+    for decl in decls {
+        write!(b, "{};", decl)?;
+    }
+    Ok(())
+}
+
+// where a pushed value comes from when it's written to its stack
+enum Slot {
+    // a temp computed before either stack changed
+    Temp(usize),
+    // computed as it's written, reading the stacks as they are after its own stack's pops
+    Direct(Value),
+}
+
+// a value read after the pops on its own stack, which only works if it doesn't read anything they removed or anything
+// that changes before it's written: the cur stack's writes come before the off stack's, so the off stack's values
+// can't read the cur stack at all
+fn rebase(v: &Value, pop: usize, is_off: bool) -> Option<Value> {
+    let parts = v.parts.iter().map(|(part, mul)| {
+        let part = match (part.clone(), is_off) {
+            (ValuePart::CurStackElem(n), false) if n >= pop => ValuePart::CurStackElem(n-pop),
+            (ValuePart::CurStackSize(n), false) if n >= pop => ValuePart::CurStackSize(n-pop),
+            (ValuePart::OffStackElem(n), true) if n >= pop => ValuePart::OffStackElem(n-pop),
+            (ValuePart::OffStackSize(n), true) if n >= pop => ValuePart::OffStackSize(n-pop),
+            (ValuePart::CurStackElem(_) | ValuePart::CurStackSize(_), _) => return None,
+            (ValuePart::OffStackElem(_) | ValuePart::OffStackSize(_), true) => return None,
+            (part, _) => part,
+        };
+        Some((part, *mul))
+    }).collect::<Option<_>>()?;
+    Some(Value { const_val: v.const_val.clone(), parts })
+}
+
+// returns where each pushed value comes from, so that pushing the same value twice as in (({})) only reads it once
+// and values that can be written straight to the stack don't need a temp at all
+fn compile_push_temps(b: &mut impl Write, decls: &mut Vec<String>, opts: &Options, push: Vec<Value>, pop: usize, is_off: bool, effect_index: usize) -> std::io::Result<Vec<Slot>> {
+    let mut slots = Vec::with_capacity(push.len());
+    for (i, elem) in push.iter().enumerate() {
+        if let Some(j) = push[..i].iter().position(|v| v == elem) {
+            slots.push(Slot::Temp(j));
+            continue;
+        }
+        if !push[i+1..].contains(elem) {
+            if let Some(v) = rebase(elem, pop, is_off) {
+                slots.push(Slot::Direct(v));
+                continue;
+            }
+        }
+        declare(b, decls, opts, "l", format!("t{}_{}", i, effect_index))?;
+        write!(b, "=")?;
+        compile_value(b, elem.clone(), opts)?;
         write!(b, ";")?;
+        slots.push(Slot::Temp(i));
     }
-    for i in 0..l {
-        write!(b, "{s}[{p}+{}]=t{}_{};", i as isize - pop as isize, i, effect_index, s=stack, p=top)?;
+    Ok(slots)
+}
+
+fn compile_guard(b: &mut impl Write, opts: &Options, n: usize, is_off: bool) -> std::io::Result<()> {
+    let (s, p, c) = if !is_off { ("s", "p", "c") } else { ("o", "d", "v") };
+    // a single doubling isn't enough when one effect pushes more than the stack's whole capacity, and toggles mean
+    // either stack can be the one that started out smaller
+    if n > opts.off_capacity.min(1024) {
+        write!(b, "if({p}+{n}>{c}){{while({p}+{n}>{c}){c}*=2;{s}=realloc({s},{c}*sizeof(l));}}")
+    } else {
+        write!(b, "if({p}+{n}>{c}){{{c}*=2;{s}=realloc({s},{c}*sizeof(l));}}")
     }
-    Ok(offset)
 }
 
-fn compile_effects(b: &mut impl Write, e: Effects) -> std::io::Result<()> {
+// guard is whether this effect has to check the stack's capacity itself rather than it being checked beforehand
+fn compile_single_stack_effect(b: &mut impl Write, opts: &Options, pop: usize, slots: Vec<Slot>, is_off: bool, guard: bool, effect_index: usize) -> std::io::Result<()> {
+    let (stack, top) = if !is_off { ("s", "p") } else { ("o", "d") };
+    // popping more than the stack holds leaves it empty rather than wrapping the pointer
+    if pop > 0 {
+        write!(b, "{p}={p}>{}?{p}-{}:0;", pop, pop, p=top)?;
+    }
+    let push = slots.len();
+    if push > 0 {
+        if guard {
+            compile_guard(b, opts, push, is_off)?;
+        }
+        // masking the two's complement representation keeps negative values in 0..255 as well
+        let mask = if opts.arithmetic == Arithmetic::Mod256 { "&255" } else { "" };
+        // a lone push like (()) is the most common effect of all, so it gets the simplest code
+        if let (0, [Slot::Direct(v)]) = (pop, &slots[..]) {
+            write!(b, "{}=", elem(opts, stack, top, true))?;
+            compile_value(b, v.clone(), opts)?;
+            return write!(b, "{};{}++;", mask, top);
+        }
+        let mut i = 0;
+        let mut slots = slots.into_iter().peekable();
+        while let Some(slot) = slots.next() {
+            let t = match slot {
+                Slot::Temp(t) => t,
+                Slot::Direct(v) => {
+                    write!(b, "{}=", elem(opts, stack, &format!("{}+{}", top, i), true))?;
+                    compile_value(b, v, opts)?;
+                    write!(b, "{};", mask)?;
+                    i += 1;
+                    continue;
+                },
+            };
+            let mut run = 1;
+            while slots.next_if(|u| matches!(u, Slot::Temp(u) if *u == t)).is_some() {
+                run += 1;
+            }
+            // long runs of the same value, like a big constant array being built, are filled in a loop
+            if run >= 4 {
+                write!(b, "{{size_t i;for(i={};i<{};i++){}=t{}_{}{};}}", i, i+run, elem(opts, stack, &format!("{}+i", top), true), t, effect_index, mask)?;
+            } else {
+                for j in i..i+run {
+                    write!(b, "{}=t{}_{}{};", elem(opts, stack, &format!("{}+{}", top, j), true), t, effect_index, mask)?;
+                }
+            }
+            i += run;
+        }
+        write!(b, "{p}+={};", push, p=top)?;
+    }
+    Ok(())
+}
+
+// an effect that only rewrites the top elements of one stack, each as itself plus something that doesn't read
+// the other rewritten elements, can update them in place when the stack is deep enough, as in ({}())
+fn in_place_deltas(pop: usize, push: &[Value], is_off: bool) -> Option<(Vec<Value>, bool)> {
+    if pop == 0 || push.len() != pop {
+        return None;
+    }
+    let elem = if is_off { ValuePart::OffStackElem } else { ValuePart::CurStackElem };
+    let deltas = push.iter().enumerate().map(|(i, v)| {
+        let mut delta = v.clone();
+        let idx = delta.parts.iter().position(|(part, mul)| *part == elem(pop-1-i) && *mul == 1)?;
+        delta.parts.remove(idx);
+        if (0..pop).any(|n| delta.parts.iter().any(|(part, _)| *part == elem(n))) {
+            return None;
+        }
+        Some(delta)
+    }).collect::<Option<_>>()?;
+    Some((deltas, is_off))
+}
+
+// writes the in-place update and opens the else branch that falls back to the general code on a shallow stack
+fn compile_in_place(b: &mut impl Write, opts: &Options, deltas: &[Value], is_off: bool) -> std::io::Result<()> {
+    let (stack, top) = if !is_off { ("s", "p") } else { ("o", "d") };
+    let k = deltas.len();
+    write!(b, "if({}>={}){{", top, k)?;
+    for (i, delta) in deltas.iter().enumerate() {
+        if delta.parts.is_empty() && delta.const_val == 0.into() {
+            continue;
+        }
+        compile_add_to(b, opts, &elem(opts, stack, &format!("{}-{}", top, k-i), false), delta.clone())?;
+    }
+    write!(b, "}}else{{")
+}
+
+fn compile_add_to(b: &mut impl Write, opts: &Options, slot: &str, delta: Value) -> std::io::Result<()> {
+    match opts.arithmetic {
+        Arithmetic::Wrapping => write!(b, "{}+=", slot)?,
+        Arithmetic::Saturating => write!(b, "{}=sa({},", slot, slot)?,
+        Arithmetic::Mod256 => write!(b, "{}=({}+", slot, slot)?,
+    }
+    compile_value(b, delta, opts)?;
+    match opts.arithmetic {
+        Arithmetic::Wrapping => write!(b, ";"),
+        Arithmetic::Saturating => write!(b, ");"),
+        Arithmetic::Mod256 => write!(b, ")&255;"),
+    }
+}
+
+fn compile_loop_limit(b: &mut impl Write, opts: &Options, i: usize) -> std::io::Result<()> {
+    if let Some(limit) = opts.loop_limit {
+        write!(b, "if(++k{}>{}{}){{fputs(\"loop iteration limit exceeded\\n\",stderr);abort();}}", i, limit, opts.c_standard.unsigned_suffix())?;
+    }
+    Ok(())
+}
+
+fn declare_loop(b: &mut impl Write, decls: &mut Vec<String>, opts: &Options, i: usize) -> std::io::Result<()> {
+    declare(b, decls, opts, "l", format!("r{}", i))?;
+    write!(b, "=0;")?;
+    if opts.loop_limit.is_some() {
+        declare(b, decls, opts, opts.c_standard.unsigned(), format!("k{}", i))?;
+        write!(b, "=0;")?;
+    }
+    Ok(())
+}
+
+fn compile_countdown(b: &mut impl Write, decls: &mut Vec<String>, l: TopLoop, opts: &Options, i: usize) -> std::io::Result<()> {
+    declare_loop(b, decls, opts, i)?;
+    // the result of counting down from n is result_const*n + result_top*n*(n+1)/2, computed unsigned so it wraps
+    // like the loop would; halving whichever of n and n+1 is even keeps the product from overflowing early
+    let top = elem(opts, "s", "p-1", false);
+    if opts.arithmetic == Arithmetic::Wrapping {
+        let u = opts.c_standard.unsigned();
+        write!(b, "if(p&&{t}>0){{{u} n={t};", t=top, u=u)?;
+        if let Some(limit) = opts.loop_limit {
+            write!(b, "if(n>{}{}){{fputs(\"loop iteration limit exceeded\\n\",stderr);abort();}}", limit, opts.c_standard.unsigned_suffix())?;
+        }
+        write!(b, "r{}=(l)(n*({u})({})+(n%2?(n+1)/2*n:n/2*(n+1))*({u})({}));{}=0;}}else ", i, l.result_const, l.result_top, top, u=u)?;
+    }
+    // a top that isn't positive has to be counted down the long way
+    write!(b, "while(p&&{}){{", top)?;
+    compile_loop_limit(b, opts, i)?;
+    compile_add_to(b, opts, &format!("r{}", i), l.result())?;
+    compile_add_to(b, opts, &top, Value { const_val: (-1).into(), parts: Vec::new() })?;
+    write!(b, "}}")
+}
+
+// the loop condition already guarantees there's something to pop, so none of the usual guards are needed
+fn compile_drain(b: &mut impl Write, decls: &mut Vec<String>, l: TopLoop, opts: &Options, i: usize) -> std::io::Result<()> {
+    declare_loop(b, decls, opts, i)?;
+    write!(b, "while(p&&{}){{", elem(opts, "s", "p-1", false))?;
+    compile_loop_limit(b, opts, i)?;
+    compile_add_to(b, opts, &format!("r{}", i), l.result())?;
+    write!(b, "p--;}}")
+}
+
+// opens a block with the number of nonzero values on top of the current stack in n, which is how many times a loop
+// that pops each of them runs
+fn compile_run_length(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    write!(b, "{{size_t n=0;while(n<p&&{})n++;", elem(opts, "s", "p-1-n", false))?;
+    if let Some(limit) = opts.loop_limit {
+        write!(b, "if(n>{}{}){{fputs(\"loop iteration limit exceeded\\n\",stderr);abort();}}", limit, opts.c_standard.unsigned_suffix())?;
+    }
+    Ok(())
+}
+
+// the run of nonzero values is measured first, so the off stack is grown once rather than checked for every value
+fn compile_transfer(b: &mut impl Write, decls: &mut Vec<String>, l: TopLoop, opts: &Options, i: usize, reverses: bool) -> std::io::Result<()> {
+    declare_loop(b, decls, opts, i)?;
+    compile_run_length(b, opts)?;
+    let mask = if opts.arithmetic == Arithmetic::Mod256 { "&255" } else { "" };
+    if reverses {
+        // moving a whole stack onto an empty one is the same as reversing it where it is and swapping the two, which
+        // needs no room on the off stack; the results are still added up from the top down, since saturating
+        // arithmetic cares about the order
+        write!(b, "if(n==p&&!d){{size_t j;while(p){{")?;
+        compile_add_to(b, opts, &format!("r{}", i), l.result())?;
+        if !mask.is_empty() {
+            write!(b, "{}&=255;", elem(opts, "s", "p-1", false))?;
+        }
+        let (low, high) = (elem(opts, "s", "j", false), elem(opts, "s", "n-1-j", false));
+        write!(b, "p--;}}p=n;for(j=0;j<n/2;j++){{l t={low};{low}={high};{high}=t;}}\
+        {{size_t t=p,g=c;l*h=s;p=d;d=t;c=v;v=g;s=o;o=h;}}}}else{{", low=low, high=high)?;
+    }
+    write!(b, "if(d+n>v){{while(d+n>v)v*=2;o=realloc(o,v*sizeof(l));}}")?;
+    // the values land in reverse order, so they can't be copied as one block
+    write!(b, "while(n--){{")?;
+    compile_add_to(b, opts, &format!("r{}", i), l.result())?;
+    write!(b, "{}={}{};p--;}}}}", elem(opts, "o", "d++", true), elem(opts, "s", "p-1", false), mask)?;
+    if reverses {
+        write!(b, "}}")?;
+    }
+    Ok(())
+}
+
+// an empty off stack reads as zero, so pushing a zero first lets the total always be kept in place
+fn compile_sum(b: &mut impl Write, decls: &mut Vec<String>, result: Value, opts: &Options, i: usize) -> std::io::Result<()> {
+    declare_loop(b, decls, opts, i)?;
+    compile_run_length(b, opts)?;
+    write!(b, "if(n&&!d){}=0;while(n--){{", elem(opts, "o", "d++", true))?;
+    compile_add_to(b, opts, &format!("r{}", i), result)?;
+    compile_add_to(b, opts, &elem(opts, "o", "d-1", false), Value { const_val: 0.into(), parts: vec![(ValuePart::CurStackElem(0), 1)] })?;
+    write!(b, "p--;}}}}")
+}
+
+fn grows(effect: &Effect) -> bool {
+    matches!(effect, Effect::Stack(s) if s.cur_push.len() > s.cur_pop || s.off_push.len() > s.off_pop)
+}
+
+// how much taller each stack can get over a run of effects without loops, with the stack that's current at the
+// start of the run first, so that a single guard before the run can cover all of it
+fn run_growth(run: &[Effect]) -> [usize; 2] {
+    let mut height = [0usize, 0];
+    let mut growth = [0, 0];
+    let mut cur = 0;
+    for effect in run {
+        // countdowns and drains never make a stack taller, and transfers and sums end the run
+        let Effect::Stack(s) = effect else { continue };
+        for (k, pop, push) in [(cur, s.cur_pop, s.cur_push.len()), (1-cur, s.off_pop, s.off_push.len())] {
+            // an upper bound, since popping from a stack that's shorter than it was at the start can't go below zero
+            height[k] = height[k].saturating_sub(pop) + push;
+            growth[k] = growth[k].max(height[k]);
+        }
+        if s.toggle {
+            cur = 1-cur;
+        }
+    }
+    growth
+}
+
+// whether a loop body takes exactly one from the top of the current stack and leaves it in the same place, in which
+// case the number of iterations is known as soon as the loop starts, even if the body does other things as well
+fn counts_down(body: &Effects) -> bool {
+    let [Effect::Stack(s)] = &body[..] else { return false };
+    let decremented = Value { const_val: (-1).into(), parts: vec![(ValuePart::CurStackElem(0), 1)] };
+    !s.toggle && s.cur_pop == s.cur_push.len() && s.cur_push.last() == Some(&decremented)
+}
+
+fn compile_effects(b: &mut impl Write, decls: &mut Vec<String>, e: Effects, opts: &Options) -> std::io::Result<()> {
+    // with a static bound on the depth, the stacks are grown once up front instead
+    let mut guarded = vec![opts.max_depth.is_none(); e.len()];
+    let mut run_guards = Vec::new();
+    if opts.max_depth.is_none() {
+        let mut start = 0;
+        while start < e.len() {
+            // transfers and sums can make the off stack taller without being stack effects, so like loops they end a run
+            let len = e[start..].iter().take_while(|effect| !matches!(effect, Effect::Loop(_) | Effect::Transfer(_) | Effect::Sum(_))).count().max(1);
+            let run = &e[start..start+len];
+            // more than one effect in a run that grows the stacks means more than one guard, which can be merged
+            if run.iter().filter(|effect| grows(effect)).count() > 1 {
+                run_guards.push((start, run_growth(run)));
+                guarded[start..start+len].fill(false);
+            }
+            start += len;
+        }
+    }
+    // a transfer that switches stacks straight afterwards is reversing the stack, most often all of it
+    let reverses: Vec<bool> = (0..e.len()).map(|i| matches!(&e[i..], [Effect::Transfer(_), Effect::Stack(s), ..] if s.toggle)).collect();
+    let mut run_guards = run_guards.into_iter().peekable();
     for (i, effect) in e.into_iter().enumerate() {
+        if let Some((_, growth)) = run_guards.next_if(|&(start, _)| start == i) {
+            for (n, is_off) in [(growth[0], false), (growth[1], true)] {
+                if n > 0 {
+                    compile_guard(b, opts, n, is_off)?;
+                }
+            }
+        }
         match effect {
-            Effect::Stack(StackEffect {
-                cur_pop,
-                cur_push,
-                off_pop,
-                off_push,
-                toggle,
-            }) => {
-                let p_offset = compile_single_stack_effect(b, cur_pop, cur_push, false, i*2)?;
-                let d_offset = compile_single_stack_effect(b, off_pop, off_push, true, i*2+1)?;
-
-                if p_offset != 0 {
-                    write!(b, "p+={};", p_offset)?;
+            Effect::Stack(effect) => {
+                let restores_off = effect.off_pop > 0 && effect.restores_off();
+                let StackEffect { cur_pop, cur_push, off_pop, off_push, toggle } = effect;
+                let in_place = if off_pop == 0 && off_push.is_empty() {
+                    in_place_deltas(cur_pop, &cur_push, false)
+                } else if cur_pop == 0 && cur_push.is_empty() {
+                    in_place_deltas(off_pop, &off_push, true)
+                } else {
+                    None
+                };
+                if let Some((deltas, is_off)) = &in_place {
+                    compile_in_place(b, opts, deltas, *is_off)?;
+                }
+                if restores_off && in_place.is_none() {
+                    // putting back what was popped only does anything when the off stack gets padded with zeros
+                    let cur_slots = compile_push_temps(b, decls, opts, cur_push, cur_pop, false, i*2)?;
+                    compile_single_stack_effect(b, opts, cur_pop, cur_slots, false, guarded[i], i*2)?;
+                    write!(b, "if(d<{}){{", off_pop)?;
+                    let off_slots = compile_push_temps(b, decls, opts, off_push, off_pop, true, i*2+1)?;
+                    compile_single_stack_effect(b, opts, off_pop, off_slots, true, guarded[i], i*2+1)?;
+                    write!(b, "}}")?;
+                } else {
+                    // every pushed value is read before either stack pointer moves
+                    let cur_slots = compile_push_temps(b, decls, opts, cur_push, cur_pop, false, i*2)?;
+                    let off_slots = compile_push_temps(b, decls, opts, off_push, off_pop, true, i*2+1)?;
+                    compile_single_stack_effect(b, opts, cur_pop, cur_slots, false, guarded[i], i*2)?;
+                    compile_single_stack_effect(b, opts, off_pop, off_slots, true, guarded[i], i*2+1)?;
                 }
-                if d_offset != 0 {
-                    write!(b, "d+={};", d_offset)?;
+                if in_place.is_some() {
+                    write!(b, "}}")?;
                 }
                 if toggle {
-                    write!(b, "{{size_t t=p;p=d;d=t;size_t g=c;c=v;v=g;l*h=s;s=o;o=h;}}")?;
+                    write!(b, "{{size_t t=p,g=c;l*h=s;p=d;d=t;c=v;v=g;s=o;o=h;}}")?;
                 }
             },
             Effect::Loop(e) => {
-                write!(b, "l r{}=0;while(p&&s[p-1]){{", i)?;
-                write!(b, "r{}+=", i)?;
-                compile_value(b, e.result)?;
-                write!(b, ";")?;
-                compile_effects(b, e.effects)?;
+                declare_loop(b, decls, opts, i)?;
+                for (n, v) in e.hoisted {
+                    declare(b, decls, opts, "l", format!("h{}", n))?;
+                    write!(b, "=")?;
+                    compile_value(b, v, opts)?;
+                    write!(b, ";")?;
+                }
+                let counted = opts.arithmetic == Arithmetic::Wrapping && counts_down(&e.effects);
+                let top = elem(opts, "s", "p-1", false);
+                if counted {
+                    // wrapping around from a negative top takes exactly as many steps as its value as unsigned
+                    write!(b, "{{{} n=p?{}:0;for(;n;n--){{", opts.c_standard.unsigned(), top)?;
+                    // which only holds if every iteration really does take one off the top
+                    if opts.debug_checks {
+                        write!(b, "assert(p&&({}){}==n);", opts.c_standard.unsigned(), top)?;
+                    }
+                } else {
+                    write!(b, "while(p&&{}){{", top)?;
+                }
+                compile_loop_limit(b, opts, i)?;
+                if opts.arithmetic == Arithmetic::Saturating {
+                    write!(b, "r{i}=sa(r{i},", i=i)?;
+                    compile_value(b, e.result, opts)?;
+                    write!(b, ");")?;
+                } else {
+                    write!(b, "r{}+=", i)?;
+                    compile_value(b, e.result, opts)?;
+                    write!(b, ";")?;
+                }
+                let mut body = Vec::new();
+                let mut body_decls = Vec::new();
+                compile_effects(&mut body, &mut body_decls, e.effects, opts)?;
+                if body_decls.is_empty() {
+                    b.write_all(&body)?;
+                } else {
+                    // an inner block, so that the body's hoisted names can't shadow this loop's own
+                    write!(b, "{{")?;
+                    write_decls(b, &body_decls)?;
+                    b.write_all(&body)?;
+                    write!(b, "}}")?;
+                }
                 write!(b, "}}")?;
+                if counted {
+                    write!(b, "}}")?;
+                }
             },
+            Effect::Countdown(l) => compile_countdown(b, decls, l, opts, i)?,
+            Effect::Drain(l) => compile_drain(b, decls, l, opts, i)?,
+            Effect::Transfer(l) => compile_transfer(b, decls, l, opts, i, reverses[i])?,
+            Effect::Sum(v) => compile_sum(b, decls, v, opts, i)?,
+        }
+        if opts.debug_checks {
+            write!(b, "assert(p<=c&&d<=v);")?;
         }
     }
     Ok(())
 }
 
-pub fn compile(b: &mut impl Write, e: Expr) -> std::io::Result<()> {
-    write!(b, "#include<stdlib.h>\n#include<string.h>\n#include<stdio.h>\n\
-    typedef long long l;\
-    int main(int argc,char**argv){{l*s=malloc(1024*sizeof(l)),*o=malloc(1024*sizeof(l));size_t p=argc-1,d=0;size_t c=1024,v=1024;\
-    for(int i=1;i<argc;i++)s[i-1]=atoll(argv[i]);")?;
-    let r = compile_effects(b, e.effects)?;
-    write!(b, r#"for(size_t i=p-1;i!=-1;i--)printf("%lld\n", s[i]);}}"#)?;
-    Ok(r)
+fn compile_input(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    // stack arguments would otherwise be silently ignored when the stack comes from somewhere else
+    match opts.input {
+        Input::StdinNumbers | Input::StdinChars => {
+            write!(b, "if(argc>1){{fputs(\"stack arguments can't be given when the stack is read from stdin\\n\",stderr);exit(1);}}")?;
+        },
+        Input::Baked => {
+            write!(b, "if(argc>1){{fputs(\"stack arguments can't be given to a program compiled with a baked-in stack\\n\",stderr);exit(1);}}")?;
+        },
+        Input::Args | Input::Line => {},
+    }
+    // every value has to start out in range too, not just the ones the program computes
+    let mask = if opts.arithmetic == Arithmetic::Mod256 { "&255" } else { "" };
+    match opts.input {
+        Input::Baked => {
+            let n = opts.baked.len();
+            if n > 1024 {
+                write!(b, "c={};s=realloc(s,c*sizeof(l));", n)?;
+            }
+            for (i, v) in opts.baked.iter().enumerate() {
+                match opts.int_type.literal(v, opts.c_standard) {
+                    Some(x) => write!(b, "s[{}]={}{};", i, x, mask)?,
+                    // left for num to reject when the program starts, like a bad argument would be
+                    None => write!(b, "s[{}]=num({}){};", i, c_string(v), mask)?,
+                }
+            }
+            write!(b, "p={};", n)
+        },
+        Input::Args => write!(b, "{{int i;for(i=1;i<argc;i++){{if(p==c){{c*=2;s=realloc(s,c*sizeof(l));}}s[p++]=num(argv[i]){};}}}}", mask),
+        // malformed tokens abort the program instead of being skipped, so bad input is never silently misread
+        Input::StdinNumbers => write!(b, "{{size_t n=0,m=1024,r;char*b=malloc(m),*t;while((r=fread(b+n,1,m-n-1,stdin))){{n+=r;if(n+1==m){{m*=2;b=realloc(b,m);}}}}b[n]=0;\
+            for(t=strtok(b,\" \\t\\n\\v\\f\\r\");t;t=strtok(0,\" \\t\\n\\v\\f\\r\")){{if(p==c){{c*=2;s=realloc(s,c*sizeof(l));}}s[p++]=num(t){};}}free(b);}}", mask),
+        // every byte is its own cell, including whitespace and newlines
+        Input::StdinChars => {
+            write!(b, "{{int ch;while((ch=getchar())!=EOF){{if(p==c){{c*=2;s=realloc(s,c*sizeof(l));}}s[p++]=ch;}}}}")?;
+            match opts.eof.sentinel() {
+                Some(x) => write!(b, "if(p==c){{c*=2;s=realloc(s,c*sizeof(l));}}s[p++]={}{};", x, mask),
+                None => Ok(()),
+            }
+        },
+        // read a character at a time rather than with getline, which is POSIX rather than standard C
+        Input::Line => write!(b, "{{size_t n=0,m=1024;int ch;char*b=malloc(m),*t;while((ch=getchar())!=EOF&&ch!=10){{if(n+1==m){{m*=2;b=realloc(b,m);}}b[n++]=ch;}}b[n]=0;\
+            for(t=strtok(b,\" \\t\\n\\v\\f\\r\");t;t=strtok(0,\" \\t\\n\\v\\f\\r\")){{if(p==c){{c*=2;s=realloc(s,c*sizeof(l));}}s[p++]=num(t){};}}free(b);}}", mask),
+    }
+}
+
+// the printf conversion and the arguments that print the value of the expression x, which is read more than once
+fn printf_value(opts: &Options, x: &str) -> (String, String) {
+    match opts.hex {
+        None => (String::from(opts.int_type.format(opts.c_standard)), String::from(x)),
+        Some(Hex { prefix, negatives }) => {
+            let prefix = if prefix { "0x" } else { "" };
+            let u = opts.c_standard.unsigned();
+            let conv = if opts.c_standard == CStandard::C89 { "%lx" } else { "%llx" };
+            if opts.int_type == IntType::I64 && negatives == HexNegatives::Sign {
+                (format!("%s{}{}", prefix, conv), format!("{x}<0?\"-\":\"\",{x}<0?-({u}){x}:({u}){x}", x=x, u=u))
+            } else {
+                (format!("{}{}", prefix, conv), format!("({}){}", u, x))
+            }
+        },
+    }
+}
+
+fn compile_stack_output(b: &mut impl Write, opts: &Options, separator: &str) -> std::io::Result<()> {
+    if opts.flush {
+        // each value reaches a pipe as soon as it's printed rather than when the buffer fills or the program exits
+        let (format, args) = printf_value(opts, "s[i]");
+        write!(b, r#"{{size_t i;for(i=p;i-->0;){{printf("{}%s",{},i?{}:"");fflush(stdout);}}}}"#, format, args, c_string(separator))
+    } else {
+        // formatting by hand into one buffer and writing it all at once is several times faster than printf per value;
+        // the 20 characters each value gets are enough for a sign, a 0x and 16 hex digits too
+        let (digits, sign) = match opts.hex {
+            Some(Hex { negatives: HexNegatives::TwosComplement, .. }) => (format!("{} u=x;", opts.c_standard.unsigned()), ""),
+            _ => opts.int_type.magnitude(opts.c_standard),
+        };
+        let (digit, base, prefix) = match opts.hex {
+            None => ("48+u%10", 10, ""),
+            Some(Hex { prefix, .. }) => ("\"0123456789abcdef\"[u%16]", 16, if prefix { "*q++=48;*q++=120;" } else { "" }),
+        };
+        write!(b, "{{char*w=malloc(p*{}+1),*q=w;size_t i;for(i=p;i-->0;){{l x=s[i];{}char g[20];int k=0;do g[k++]={};while(u/={});{}{}while(k)*q++=g[--k];\
+            if(i){{memcpy(q,{},{});q+={};}}}}fwrite(w,1,q-w,stdout);free(w);}}",
+            20+separator.len(), digits, digit, base, sign, prefix, c_string(separator), separator.len(), separator.len())
+    }
+}
+
+// the prototype of a function compiled with Options::function, which is given the initial stack and returns the final
+// stack in a new array, both bottom first
+pub fn function_signature(name: &str, int_type: IntType, c_standard: CStandard) -> String {
+    let ty = int_type.c_type(c_standard);
+    format!("void {}(const {ty}*in,size_t n,{ty}**out,size_t*out_n)", name, ty=ty)
+}
+
+// a header declaring a function compiled with Options::function, for C callers to include
+pub fn write_header(b: &mut impl Write, name: &str, int_type: IntType, c_standard: CStandard) -> std::io::Result<()> {
+    let guard = format!("{}_H", name.to_ascii_uppercase());
+    writeln!(b, "#ifndef {}\n#define {}\n", guard, guard)?;
+    writeln!(b, "#include <stddef.h>\n")?;
+    writeln!(b, "#ifdef __cplusplus\nextern \"C\" {{\n#endif\n")?;
+    writeln!(b, "/* runs the program on the initial stack in[0..n], storing the final stack in a new array *out that the caller")?;
+    writeln!(b, "   must free, with its length in *out_n; both stacks are bottom first */")?;
+    writeln!(b, "{};\n", function_signature(name, int_type, c_standard))?;
+    writeln!(b, "#ifdef __cplusplus\n}}\n#endif\n")?;
+    writeln!(b, "#endif")
+}
+
+// the includes, helpers and stacks that every program starts with
+fn write_prologue(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    write!(b, "#include<stdlib.h>\n#include<string.h>\n#include<stdio.h>\n#include<errno.h>\n#include<limits.h>\n")?;
+    if opts.debug_checks {
+        writeln!(b, "#include<assert.h>")?;
+    }
+    if opts.dump_on_interrupt {
+        writeln!(b, "#include<signal.h>")?;
+    }
+    write!(b, "typedef {} l;", opts.int_type.c_type(opts.c_standard))?;
+    // a function has its stack passed in rather than parsed
+    if opts.function.is_none() {
+        write!(b, "l num(const char*t){{char*e;l x;errno=0;x={strto}(t,&e,10);\
+        if(e==t||*e){{fprintf(stderr,\"invalid number in input: %s\\n\",t);exit(1);}}\
+        if(errno==ERANGE){{fprintf(stderr,\"number out of range in input: %s\\n\",t);exit(1);}}return x;}}", strto=opts.int_type.parse_fn(opts.c_standard))?;
+    }
+    // a function is linked into a bigger program, where the helpers mustn't clash with anything
+    let linkage = if opts.function.is_some() { "static " } else { "" };
+    if opts.arithmetic == Arithmetic::Saturating {
+        let (min, max) = opts.c_standard.limits();
+        write!(b, "{0}l sa(l a,l b){{if(b>0&&a>{2}-b)return {2};if(b<0&&a<{1}-b)return {1};return a+b;}}\
+        {0}l sm(l a,l b){{if(a>0?(b>0?a>{2}/b:b<{1}/a):(b>0?a<{1}/b:a&&b<{2}/a))return(a>0)==(b>0)?{2}:{1};return a*b;}}", linkage, min, max)?;
+    }
+    // the index is printed signed, so that reading below the bottom of a stack shows up as a negative index
+    if opts.debug_checks {
+        write!(b, "{}l*ck(l*a,size_t i,size_t n,const char*m){{if(i>=n){{fprintf(stderr,\"%s (index %ld, limit %lu)\\n\",m,(long)i,(unsigned long)n);\
+        abort();}}return a+i;}}", linkage)?;
+    }
+    // the handler sees main's stack and its size through pointers, and since toggles swap what those variables hold
+    // rather than the variables themselves, it always sees the active stack; fprintf isn't safe to call from a signal
+    // handler, but the program is about to be killed anyway
+    if opts.dump_on_interrupt {
+        write!(b, "static l**is;static size_t*ip,*id;static void dump(int g){{size_t i;\
+        fprintf(stderr,\"\\ninterrupted with %lu values on the active stack and %lu on the off stack\\n\",(unsigned long)*ip,(unsigned long)*id);\
+        for(i=0;i<10&&i<*ip;i++)fprintf(stderr,\"{}\\n\",(*is)[*ip-1-i]);if(*ip>10)fputs(\"...\\n\",stderr);signal(g,SIG_DFL);raise(g);}}",
+            opts.int_type.format(opts.c_standard))?;
+    }
+    // the stacks, their sizes and every loop variable are locals, and nothing is static, so each call of a function
+    // starts from scratch and calls can't interfere with each other
+    let alloc = |n: &str| if opts.zero_init { format!("calloc({},sizeof(l))", n) } else { format!("malloc({}*sizeof(l))", n) };
+    if let Some(name) = &opts.function {
+        write!(b, "{}{{l*s={},*o={};size_t p=n,d=0;size_t c=n>1024?n:1024,v={};",
+            function_signature(name, opts.int_type, opts.c_standard), alloc("(n>1024?n:1024)"), alloc(&opts.off_capacity.to_string()), opts.off_capacity)?;
+    } else {
+        write!(b, "int main(int argc,char**argv){{l*s={},*o={};size_t p=0,d=0;size_t c=1024,v={};",
+            alloc("1024"), alloc(&opts.off_capacity.to_string()), opts.off_capacity)?;
+    }
+    Ok(())
+}
+
+// fills the active stack with the input, which comes after any declarations the program needs, along with anything
+// else that has to be set up before the program runs
+fn write_input(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    if opts.dump_on_interrupt {
+        write!(b, "is=&s;ip=&p;id=&d;signal(SIGINT,dump);")?;
+    }
+    if opts.function.is_some() {
+        write!(b, "if(n)memcpy(s,in,n*sizeof(l));")?;
+        if opts.arithmetic == Arithmetic::Mod256 {
+            write!(b, "{{size_t i;for(i=0;i<n;i++)s[i]&=255;}}")?;
+        }
+        Ok(())
+    } else {
+        compile_input(b, opts)
+    }
+}
+
+// hands the final stack back, by printing it or giving it to the caller of a function
+fn write_epilogue(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    if opts.function.is_some() {
+        // the caller owns the final stack from here on, and frees it
+        return write!(b, "free(o);*out=s;*out_n=p;}}");
+    }
+    match opts.output {
+        Output::Stack => {
+            compile_stack_output(b, opts, &opts.separator)?;
+            match opts.trailing_newline {
+                TrailingNewline::Never => {},
+                TrailingNewline::IfNotEmpty => write!(b, "if(p)putchar(10);")?,
+                TrailingNewline::Always => write!(b, "putchar(10);")?,
+            }
+        },
+        Output::Top => {
+            let (format, args) = printf_value(opts, "(p?s[p-1]:0)");
+            write!(b, r#"printf("{}",{});"#, format, args)?;
+            // there's always a value to print, even if it's the 0 of an empty stack
+            if opts.trailing_newline != TrailingNewline::Never {
+                write!(b, "putchar(10);")?;
+            }
+        },
+        Output::Json => {
+            write!(b, "putchar(91);")?;
+            compile_stack_output(b, opts, ",")?;
+            write!(b, "putchar(93);putchar(10);")?;
+        },
+        // the final stack is still read, so the C compiler can't decide that nothing the program does matters
+        Output::None => write!(b, "{{volatile size_t u=p;volatile l x=p?s[p-1]:0;(void)u;(void)x;}}return 0;")?,
+    }
+    if opts.flush {
+        write!(b, "fflush(stdout);")?;
+    }
+    // only C99 makes falling off the end of main return 0
+    if opts.c_standard == CStandard::C89 && opts.output != Output::None {
+        write!(b, "return 0;")?;
+    }
+    write!(b, "}}")?;
+    Ok(())
+}
+
+pub fn compile(b: &mut impl Write, e: Expr, opts: &Options) -> std::io::Result<()> {
+    let opts = &Options { max_depth: analyze::analyze(&e).max_depth, ..opts.clone() };
+    write_prologue(b, opts)?;
+    let mut body = Vec::new();
+    let mut decls = Vec::new();
+    compile_effects(&mut body, &mut decls, e.effects, opts)?;
+    write_decls(b, &decls)?;
+    write_input(b, opts)?;
+    if let Some(depth) = opts.max_depth {
+        write!(b, "if(p+{n}>c){{c=p+{n};s=realloc(s,c*sizeof(l));}}if(p+{n}>v){{v=p+{n};o=realloc(o,v*sizeof(l));}}", n=depth)?;
+    }
+    b.write_all(&body)?;
+    write_epilogue(b, opts)
+}
+
+/// Writes the C that [`compile`] puts around every program under these options, with a comment where the program's own
+/// code would go.
+///
+/// `s` and `o` are the active and off stacks, with `p` and `d` values on them and room for `c` and `v`. The input is on
+/// `s` before the program runs, and whatever is left there afterwards is the output.
+pub fn write_runtime(b: &mut impl Write, opts: &Options) -> std::io::Result<()> {
+    write_prologue(b, opts)?;
+    write_input(b, opts)?;
+    write!(b, "\n/* the program goes here */\n")?;
+    write_epilogue(b, opts)
 }
@@ -0,0 +1,210 @@
+use crate::ast::{Ast, Inst};
+use crate::gen::{Input, Eof, Output, Hex, HexNegatives, TrailingNewline};
+use num_bigint::BigInt;
+use std::io::{Read, Write};
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // the most steps the program can take before it's stopped, where every instruction and loop iteration is a step
+    pub step_limit: Option<u64>,
+    // the most iterations any one run of a loop can take, like the guard a compiled program gets from --loop-limit
+    pub loop_limit: Option<u64>,
+    // print every instruction as it finishes to stderr, along with its value and both stacks
+    pub trace: bool,
+}
+
+// why the program was stopped before it finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stopped {
+    // it ran for longer than the step limit allows
+    StepLimit(u64),
+    // one loop ran for more iterations than the loop limit allows
+    LoopLimit(u64),
+}
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Stopped::StepLimit(limit) => write!(f, "program exceeded the step limit of {}", limit),
+            // the same message a compiled program aborts with
+            Stopped::LoopLimit(_) => write!(f, "loop iteration limit exceeded"),
+        }
+    }
+}
+
+// the stacks of a running program, bottom first
+struct State<'a> {
+    cur: Vec<BigInt>,
+    off: Vec<BigInt>,
+    steps: u64,
+    opts: &'a Options,
+}
+
+fn write_stack(f: &mut String, stack: &[BigInt]) {
+    f.push('[');
+    for (i, v) in stack.iter().enumerate() {
+        if i > 0 {
+            f.push(',');
+        }
+        f.push_str(&v.to_string());
+    }
+    f.push(']');
+}
+
+impl State<'_> {
+    fn trace(&self, inst: &Inst, value: &BigInt) {
+        let name = match inst {
+            Inst::One => "ONE",
+            Inst::Size => "SIZE",
+            Inst::Pop => "POP",
+            Inst::Toggle => "TOGGLE",
+            Inst::Push(_) => "PUSH",
+            Inst::Negate(_) => "NEGATE",
+            Inst::Loop(_) => "LOOP",
+            Inst::Exec(_) => "EXEC",
+        };
+        let mut line = format!("{} {} | s=", name, value);
+        write_stack(&mut line, &self.cur);
+        line.push_str(" o=");
+        write_stack(&mut line, &self.off);
+        eprintln!("{}", line);
+    }
+
+    fn step(&mut self) -> Result<(), Stopped> {
+        self.steps += 1;
+        match self.opts.step_limit {
+            Some(limit) if self.steps > limit => Err(Stopped::StepLimit(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn eval(ast: &Ast, st: &mut State) -> Result<BigInt, Stopped> {
+    let mut r = BigInt::from(0);
+    for node in ast {
+        st.step()?;
+        let v = match &node.inst {
+            Inst::One => BigInt::from(1),
+            Inst::Size => BigInt::from(st.cur.len()),
+            Inst::Pop => st.cur.pop().unwrap_or_default(),
+            Inst::Toggle => {
+                std::mem::swap(&mut st.cur, &mut st.off);
+                BigInt::from(0)
+            },
+            Inst::Push(a) => {
+                let v = eval(a, st)?;
+                st.cur.push(v.clone());
+                v
+            },
+            Inst::Negate(a) => -eval(a, st)?,
+            // an empty stack has a zero on top as far as loops are concerned
+            Inst::Loop(a) => {
+                let mut sum = BigInt::from(0);
+                let mut iterations = 0;
+                while st.cur.last().is_some_and(|v| *v != BigInt::from(0)) {
+                    st.step()?;
+                    iterations += 1;
+                    match st.opts.loop_limit {
+                        Some(limit) if iterations > limit => return Err(Stopped::LoopLimit(limit)),
+                        _ => {},
+                    }
+                    sum += eval(a, st)?;
+                }
+                sum
+            },
+            Inst::Exec(a) => {
+                eval(a, st)?;
+                BigInt::from(0)
+            },
+        };
+        if st.opts.trace {
+            st.trace(&node.inst, &v);
+        }
+        r += v;
+    }
+    Ok(r)
+}
+
+// runs the program on unbounded integers, returning the final active and inactive stacks, bottom first like the input
+pub fn run_full(ast: &Ast, input: &[BigInt], opts: &Options) -> Result<(Vec<BigInt>, Vec<BigInt>), Stopped> {
+    let mut st = State { cur: input.to_vec(), off: Vec::new(), steps: 0, opts };
+    eval(ast, &mut st)?;
+    Ok((st.cur, st.off))
+}
+
+// the final active stack alone, which is what a program outputs
+pub fn run(ast: &Ast, input: &[BigInt], opts: &Options) -> Result<Vec<BigInt>, Stopped> {
+    run_full(ast, input, opts).map(|(cur, _)| cur)
+}
+
+fn parse_numbers<'a>(tokens: impl Iterator<Item = &'a str>) -> Result<Vec<BigInt>, String> {
+    tokens.map(|t| t.parse().map_err(|_| format!("invalid number in input: {}", t))).collect()
+}
+
+// reads the initial stack the way a compiled program would with the same input mode, so the two always agree
+pub fn read_input(input: Input, args: &[String], eof: Eof) -> Result<Vec<BigInt>, String> {
+    let mut stdin = std::io::stdin();
+    match input {
+        // the interpreter has no command line of the program's own, so the values given to --args take its place
+        Input::Args | Input::Baked => parse_numbers(args.iter().map(String::as_str)),
+        Input::StdinNumbers => {
+            let mut s = String::new();
+            stdin.read_to_string(&mut s).map_err(|e| e.to_string())?;
+            parse_numbers(s.split_whitespace())
+        },
+        Input::StdinChars => {
+            let mut b = Vec::new();
+            stdin.read_to_end(&mut b).map_err(|e| e.to_string())?;
+            Ok(b.into_iter().map(BigInt::from).chain(eof.sentinel().map(BigInt::from)).collect())
+        },
+        Input::Line => {
+            let mut s = String::new();
+            stdin.read_line(&mut s).map_err(|e| e.to_string())?;
+            parse_numbers(s.split_whitespace())
+        },
+    }
+}
+
+// reads the initial stack from whitespace-separated integers in a file, bottom first
+pub fn read_input_file(path: &str) -> Result<Vec<BigInt>, String> {
+    let s = std::fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+    parse_numbers(s.split_whitespace()).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn format_value(v: &BigInt, hex: Option<Hex>) -> String {
+    match hex {
+        None => v.to_string(),
+        Some(Hex { prefix, negatives }) => {
+            let prefix = if prefix { "0x" } else { "" };
+            match negatives {
+                HexNegatives::Sign => format!("{}{}{:x}", if v.sign() == num_bigint::Sign::Minus { "-" } else { "" }, prefix, v.magnitude()),
+                // the low 64 bits, which is what a compiled program has for any value that fits
+                HexNegatives::TwosComplement => format!("{}{:x}", prefix, v & BigInt::from(u64::MAX)),
+            }
+        },
+    }
+}
+
+// prints the final stack, given bottom first, the way a compiled program would with the same output options
+pub fn write_output(b: &mut impl Write, stack: &[BigInt], opts: &crate::gen::Options) -> std::io::Result<()> {
+    let values: Vec<String> = stack.iter().rev().map(|v| format_value(v, opts.hex)).collect();
+    match opts.output {
+        Output::Stack => {
+            write!(b, "{}", values.join(&opts.separator))?;
+            match opts.trailing_newline {
+                TrailingNewline::Never => Ok(()),
+                TrailingNewline::IfNotEmpty if values.is_empty() => Ok(()),
+                _ => writeln!(b),
+            }
+        },
+        Output::Top => {
+            write!(b, "{}", values.first().cloned().unwrap_or_else(|| format_value(&BigInt::from(0), opts.hex)))?;
+            if opts.trailing_newline != TrailingNewline::Never {
+                writeln!(b)?;
+            }
+            Ok(())
+        },
+        Output::Json => writeln!(b, "[{}]", values.join(",")),
+        Output::None => Ok(()),
+    }
+}
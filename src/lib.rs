@@ -0,0 +1,48 @@
+pub mod ast;
+pub mod parser;
+pub mod gen;
+pub mod stats;
+pub mod explain;
+pub mod dot;
+pub mod analyze;
+pub mod interp;
+pub mod color;
+pub mod encode;
+
+pub use parser::ParseError;
+pub use ast::{Ast, Node, Inst, Expr, Effect, Effects, StackEffect, TopLoop, Value, ValuePart};
+pub use stats::{count_instructions, count_instructions_by_kind};
+use num_bigint::BigInt;
+
+/// Compiles Brain-Flak source to C with the default options, without writing any files or running a C compiler.
+///
+/// On failure, every diagnostic found is returned, at least one of which is an error.
+pub fn compile_to_string(src: &str) -> Result<String, Vec<ParseError>> {
+    let mut parser = parser::Parser::new(src, parser::Options::default());
+    let Some(tree) = parser.parse() else { return Err(parser.into_diagnostics()) };
+    let mut lints = Vec::new();
+    let code = ast::translate(tree, &mut lints);
+    if !parser.lint(&lints) {
+        return Err(parser.into_diagnostics());
+    }
+    let mut b = Vec::new();
+    gen::compile(&mut b, code, &gen::Options::default()).expect("writing to a Vec can't fail");
+    // non-ASCII characters only ever appear escaped in the generated C
+    Ok(String::from_utf8(b).expect("generated C is ASCII"))
+}
+
+/// Parses Brain-Flak source and runs it with the interpreter, without compiling it to C.
+///
+/// The input is the initial stack and the result is the final active stack, both bottom first.
+pub fn run_in_memory(src: &str, input: &[BigInt]) -> Result<Vec<BigInt>, Vec<ParseError>> {
+    run_in_memory_full(src, input).map(|(active, _)| active)
+}
+
+/// Like [`run_in_memory`], but returns the final inactive stack as well as the active one, as `(active, inactive)`.
+pub fn run_in_memory_full(src: &str, input: &[BigInt]) -> Result<(Vec<BigInt>, Vec<BigInt>), Vec<ParseError>> {
+    let mut parser = parser::Parser::new(src, parser::Options::default());
+    match parser.parse() {
+        Some(tree) => Ok(interp::run_full(&tree, input, &interp::Options::default()).expect("there are no limits by default")),
+        None => Err(parser.into_diagnostics()),
+    }
+}
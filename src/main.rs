@@ -1,45 +1,934 @@
-#![feature(let_else)]
+use flakc::{ast, parser, gen, stats, explain, dot, analyze, interp, encode};
+use std::fs;
+use std::io::Write;
+use flakc::color::Colorize;
+use std::str::FromStr;
 
-mod ast;
-mod parser;
-mod gen;
+// everything flakc can output, in pipeline order: the C-based outputs are compiled further by a C compiler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    CfgDot,
+    C,
+    Asm,
+    Llvm,
+    Obj,
+    Bin,
+    // the C around every program, without one
+    Runtime,
+}
 
-use std::fs;
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Emit, String> {
+        match s {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" | "ast-json" => Ok(Emit::Ast),
+            "ir" => Ok(Emit::Ir),
+            "cfg-dot" => Ok(Emit::CfgDot),
+            "c" => Ok(Emit::C),
+            "asm" => Ok(Emit::Asm),
+            "llvm" => Ok(Emit::Llvm),
+            "obj" => Ok(Emit::Obj),
+            "bin" => Ok(Emit::Bin),
+            "runtime" => Ok(Emit::Runtime),
+            _ => Err(format!("unknown emit kind `{}` (expected bin, c, asm, llvm, obj, tokens, ast, ir, cfg-dot or runtime)", s)),
+        }
+    }
+}
+
+impl Emit {
+    // what to put on the end of an input's name to get the name of its output, when there are several inputs
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Tokens => ".tokens",
+            Emit::Ast => ".json",
+            Emit::Ir => ".ir",
+            Emit::CfgDot => ".dot",
+            Emit::C | Emit::Runtime => ".c",
+            Emit::Asm => ".s",
+            Emit::Llvm => ".ll",
+            Emit::Obj => ".o",
+            Emit::Bin => "",
+        }
+    }
+
+    // the compiler and extra flags that turn the generated C into this output
+    fn compiler(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Emit::Asm => Some(("gcc", &["-S"])),
+            // an object file is compiled but not linked, so it can be linked into something else
+            Emit::Obj => Some(("gcc", &["-c"])),
+            Emit::Llvm => Some(("clang", &["-S", "-emit-llvm"])),
+            Emit::Bin => Some(("gcc", &[])),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Lines,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "lines" => Ok(OutputFormat::Lines),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format `{}` (expected lines, json or csv)", s)),
+        }
+    }
+}
 
 #[derive(argh::FromArgs)]
 /// Compile Brain-Flak code.
 struct Args {
-    /// output C source code instead of a binary
+    /// output C source code instead of a binary (same as --emit c)
     #[argh(switch, short = 'c')]
     output_c: bool,
 
-    /// file to compile
+    /// what to output: bin (default), c, asm, llvm, obj, tokens, ast (JSON), ir, cfg-dot, or runtime for the C that every
+    /// program is compiled into, which needs no input file
+    #[argh(option, default = "Emit::Bin")]
+    emit: Emit,
+
+    /// indent JSON output for reading
+    #[argh(switch)]
+    pretty: bool,
+
+    /// read the initial stack from whitespace-separated integers on stdin instead of from arguments (a malformed number is an error)
+    #[argh(switch)]
+    stdin_numbers: bool,
+
+    /// read the initial stack from the bytes of stdin, one character code per value
+    #[argh(switch)]
+    stdin_chars: bool,
+
+    /// bake these values into the binary as its initial stack, bottom first (can be repeated, and each can hold several whitespace-separated values)
+    #[argh(option)]
+    args: Vec<String>,
+
+    /// what --stdin-chars pushes after the last character: none (default), zero or neg1
+    #[argh(option, default = "gen::Eof::None")]
+    eof: gen::Eof,
+
+    /// embed these values in the binary as its initial stack, so it runs without arguments or stdin (same as --args, which it can't be used with)
+    #[argh(option)]
+    bake_input: Vec<String>,
+
+    /// read the initial stack from a single line of whitespace-separated integers on stdin
+    #[argh(switch)]
+    line_input: bool,
+
+    /// string printed between output values (default: newline)
+    #[argh(option, default = r#"String::from("\n")"#)]
+    separator: String,
+
+    /// print all output values on one line separated by spaces, with no trailing newline (recommended for machine-readable output)
+    #[argh(switch)]
+    space_separated: bool,
+
+    /// how the generated program prints the final stack: lines (default), json or csv
+    #[argh(option, default = "OutputFormat::Lines")]
+    output_format: OutputFormat,
+
+    /// print the final stack as comma-separated values on one line (same as --output-format csv)
+    #[argh(switch)]
+    csv: bool,
+
+    /// flush output after every value printed
+    #[argh(switch)]
+    flush: bool,
+
+    /// print nothing when the program finishes, to time its computation without its output
+    #[argh(switch)]
+    no_output: bool,
+
+    /// print values in hexadecimal
+    #[argh(switch)]
+    hex: bool,
+
+    /// with --hex, write 0x before each value
+    #[argh(switch)]
+    hex_prefix: bool,
+
+    /// with --hex, how negative values are written: sign (default) for a minus and the magnitude, or twos-complement
+    /// for the bits of the 64-bit value
+    #[argh(option)]
+    hex_negatives: Option<gen::HexNegatives>,
+
+    /// print only the value on top of the active stack (0 if it is empty)
+    #[argh(switch)]
+    top_only: bool,
+
+    /// abort the program if any single loop runs more than this many iterations, in the interpreter as well as compiled programs
+    #[argh(option)]
+    loop_limit: Option<u64>,
+
+    /// check every stack access the program makes, aborting with the stack and index instead of reading or writing out
+    /// of bounds, and assert what the optimizations assume (slower, for finding bugs in flakc)
+    #[argh(switch)]
+    debug_checks: bool,
+
+    /// allocate the stacks zeroed, so that a bug that reads past the top of one reads the same thing every time
+    #[argh(switch)]
+    zero_init: bool,
+
+    /// how many values the off stack has room for before it has to grow (default: 1024, the same as the active stack)
+    #[argh(option)]
+    off_capacity: Option<usize>,
+
+    /// when the program is interrupted with Ctrl-C, print how deep both stacks are and the top of the active stack
+    /// before exiting, to see where a program that seems stuck is
+    #[argh(switch)]
+    dump_on_interrupt: bool,
+
+    /// use unsigned 64-bit values, which wrap around on overflow and when going below zero
+    #[argh(switch)]
+    uint64: bool,
+
+    /// clamp arithmetic to the range of a signed 64-bit integer instead of wrapping around
+    #[argh(switch)]
+    saturating: bool,
+
+    /// reduce every pushed value modulo 256, like byte cells
+    #[argh(switch)]
+    mod256: bool,
+
+    /// C standard the generated code must conform to: c89 or c99 (default); C89 has no long long, so under c89 values are a long, which is only 64 bits where the platform's long is
+    #[argh(option, default = "gen::CStandard::C99")]
+    c_standard: gen::CStandard,
+
+    /// don't run this optimization pass: fold-negations, loop-idioms or hoist (can be repeated; also written -fno-<pass>)
+    #[argh(option)]
+    fno: Vec<ast::Pass>,
+
+    /// run exactly these optimization passes, as a comma-separated list in the order to run them, where an empty list
+    /// runs none (also written --opt-passes=<list>)
+    #[argh(option)]
+    opt_passes: Option<String>,
+
+    /// run the program with the interpreter instead of compiling it, on unbounded integers (the initial stack is read as the binary would read it, with --args standing in for its arguments)
+    #[argh(switch)]
+    interpret: bool,
+
+    /// with --interpret, read the initial stack from whitespace-separated integers in this file, bottom first
+    #[argh(option)]
+    input_file: Option<String>,
+
+    /// with --interpret, write the program's output to this file instead of stdout, away from any diagnostics
+    #[argh(option)]
+    run_output: Option<String>,
+
+    /// stop the interpreter with an error after this many steps, counting every instruction and loop iteration
+    #[argh(option)]
+    step_limit: Option<u64>,
+
+    /// print each instruction the interpreter finishes to stderr, with its value and the active (s) and off (o) stacks
+    #[argh(switch)]
+    trace: bool,
+
+    /// after the interpreter prints the active stack, print an empty line and then the inactive stack
+    #[argh(switch)]
+    dump_both: bool,
+
+    /// compile to a C function with this name instead of a program, as void NAME(const long long *in, size_t n, long long **out, size_t *out_n), which takes the initial stack and returns the final one in an array the caller frees (both bottom first); use with --emit c or obj
+    #[argh(option)]
+    emit_function: Option<String>,
+
+    /// with --emit-function, also write a header declaring the function to this file
+    #[argh(option)]
+    emit_header: Option<String>,
+
+    /// print the settings in effect, after defaults and the environment are applied, and exit
+    #[argh(switch)]
+    print_config: bool,
+
+    /// print instruction counts and optimizer statistics instead of compiling
+    #[argh(switch)]
+    stats: bool,
+
+    /// print what static analysis can tell about the program instead of compiling
+    #[argh(switch)]
+    analyze: bool,
+
+    /// don't report the warning with this code
+    #[argh(option)]
+    allow: Vec<String>,
+
+    /// treat the warning with this code as an error
+    #[argh(option)]
+    deny: Vec<String>,
+
+    /// don't warn about junk characters near instructions (same as --allow W0001 --allow W0002)
+    #[argh(switch)]
+    no_warn_junk: bool,
+
+    /// reject any junk characters outside of comments
+    #[argh(switch)]
+    strict: bool,
+
+    /// which characters are brackets: brain-flak (default) or flakcents, which writes them as accented vowels
+    #[argh(option, default = "parser::Dialect::BrainFlak")]
+    dialect: parser::Dialect,
+
+    /// how diagnostics are printed: human (default), short for one `file:line:col: level: message` line each, or json for
+    /// one object per line
+    #[argh(option, default = "parser::DiagnosticFormat::Human")]
+    diagnostic_format: parser::DiagnosticFormat,
+
+    /// print a detailed explanation of a diagnostic code and exit
+    #[argh(option)]
+    explain: Option<String>,
+
+    /// print a short program that pushes this integer and exit
+    #[argh(option)]
+    encode: Option<num_bigint::BigInt>,
+
+    /// the C compiler to use (default: $FLAKC_CC, or else gcc, or clang for --emit llvm)
+    #[argh(option)]
+    cc: Option<String>,
+
+    /// pass this flag to the C compiler, after -O2 so it can override it (can be repeated; replaces $FLAKC_FLAGS, a
+    /// list of flags that's split like a shell would)
+    #[argh(option)]
+    cflag: Vec<String>,
+
+    /// compile for this target triple, like aarch64-linux-gnu, using clang's --target or the gcc cross compiler named after it
+    #[argh(option)]
+    target: Option<String>,
+
+    /// reuse outputs from earlier compiles of the same source with the same options, which are kept in this directory
+    /// (the oldest are deleted once there are more than 256)
+    #[argh(option)]
+    cache_dir: Option<String>,
+
+    /// keep the intermediate C file that's passed to the C compiler instead of deleting it afterwards
+    #[argh(switch)]
+    keep_temps: bool,
+
+    /// link the binary statically, so it runs without any shared libraries
+    #[argh(switch, long = "static")]
+    static_link: bool,
+
+    /// files to compile, where several are compiled in parallel, each to an output named after it
     #[argh(positional)]
-    input: String,
+    input: Vec<String>,
 
-    /// name of output file
-    #[argh(option, default = r#"String::from("a.out")"#, short = 'o')]
-    output: String,
+    /// name of output file (default: a.out)
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+}
+
+// argh has no syntax for -fno-<pass> or --opt-passes=<list>, so they're rewritten into the options it does understand;
+// anything else starting with -fno-, like a flag for the C compiler, is left alone
+fn parse_args() -> Args {
+    let strings: Vec<String> = std::env::args().collect();
+    let mut rest = Vec::new();
+    for (i, arg) in strings.iter().enumerate().skip(1) {
+        let is_value = matches!(strings[i-1].as_str(), "--cflag" | "--cc");
+        let pass = arg.strip_prefix("-fno-").filter(|p| !is_value && p.parse::<ast::Pass>().is_ok());
+        if let Some(pass) = pass {
+            rest.extend(["--fno", pass]);
+        } else if let Some(list) = arg.strip_prefix("--opt-passes=") {
+            rest.extend(["--opt-passes", list]);
+        } else {
+            rest.push(arg);
+        }
+    }
+    let cmd = std::path::Path::new(&strings[0]).file_name().and_then(|s| s.to_str()).unwrap_or(&strings[0]);
+    <Args as argh::FromArgs>::from_args(&[cmd], &rest).unwrap_or_else(|early_exit| match early_exit.status {
+        Ok(()) => {
+            println!("{}", early_exit.output);
+            std::process::exit(0);
+        },
+        Err(()) => {
+            eprintln!("{}", early_exit.output);
+            std::process::exit(1);
+        },
+    })
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}: {}", "error".red().bold(), msg);
+    std::process::exit(1);
+}
+
+// FNV-1a, which unlike a HashMap's hasher is fixed, so the same strings hash the same in every build of flakc
+fn hash_of(parts: &[&str]) -> u64 {
+    let mut h = 0xcbf29ce484222325u64;
+    for part in parts {
+        // each part's length keeps ("ab", "c") and ("a", "bc") apart
+        for &b in (part.len() as u64).to_le_bytes().iter().chain(part.as_bytes()) {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    }
+    h
+}
+
+fn parser_options(args: &Args) -> parser::Options {
+    let mut allow = args.allow.clone();
+    if args.no_warn_junk {
+        allow.extend(["W0001", "W0002"].map(String::from));
+    }
+    parser::Options { allow, deny: args.deny.clone(), strict: args.strict, dialect: args.dialect, diagnostic_format: args.diagnostic_format }
+}
+
+// the values given to --args or --bake-input, which are two names for the same option, one string per value
+fn baked_values(args: &Args) -> Vec<String> {
+    let (flag, given) = match (args.args.is_empty(), args.bake_input.is_empty()) {
+        (_, true) => ("--args", &args.args),
+        (true, false) => ("--bake-input", &args.bake_input),
+        (false, false) => fail("--args and --bake-input can't be used together"),
+    };
+    let values: Vec<String> = given.iter().flat_map(|a| a.split_whitespace()).map(String::from).collect();
+    // checked here so that a typo is a compile error rather than something the binary only reports when run
+    // (the interpreter has no limit on the size of values, so it checks them itself)
+    for v in values.iter().filter(|_| !args.interpret) {
+        let valid = if args.uint64 { v.parse::<u64>().is_ok() } else { v.parse::<i64>().is_ok() };
+        if !valid {
+            fail(&format!("invalid value `{}` in {}", v, flag));
+        }
+    }
+    values
+}
+
+fn gen_options(args: &Args) -> gen::Options {
+    let baked = baked_values(args);
+    let input = match (args.stdin_numbers, args.stdin_chars, args.line_input, !baked.is_empty()) {
+        (false, false, false, false) => gen::Input::Args,
+        (true, false, false, false) => gen::Input::StdinNumbers,
+        (false, true, false, false) => gen::Input::StdinChars,
+        (false, false, true, false) => gen::Input::Line,
+        (false, false, false, true) => gen::Input::Baked,
+        _ => fail("only one of --stdin-numbers, --stdin-chars, --line-input and --args or --bake-input can be used"),
+    };
+    if args.eof != gen::Eof::None && input != gen::Input::StdinChars {
+        fail("--eof can only be used with --stdin-chars");
+    }
+    if args.run_output.is_some() && !args.interpret {
+        fail("--run-output can only be used with --interpret");
+    }
+    if args.step_limit.is_some() && !args.interpret {
+        fail("--step-limit can only be used with --interpret");
+    }
+    if args.trace && !args.interpret {
+        fail("--trace can only be used with --interpret");
+    }
+    if args.dump_both && !args.interpret {
+        fail("--dump-both can only be used with --interpret");
+    }
+    if args.input_file.is_some() {
+        if !args.interpret {
+            fail("--input-file can only be used with --interpret");
+        }
+        if input != gen::Input::Args {
+            fail("--input-file can't be used with another way of giving input");
+        }
+    }
+    if let Some(name) = &args.emit_function {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            fail(&format!("`{}` isn't a valid C function name", name));
+        }
+        if input != gen::Input::Args {
+            fail("--emit-function can't be used with an input mode, since the function is given its initial stack");
+        }
+        if args.top_only || args.csv || args.space_separated || args.no_output || args.hex || args.output_format != OutputFormat::Lines {
+            fail("--emit-function can't be used with an output option, since the function returns its final stack");
+        }
+        if args.dump_on_interrupt {
+            fail("--dump-on-interrupt can't be used with --emit-function, since its handler needs a main to find the stacks from");
+        }
+    }
+    if args.saturating && args.uint64 {
+        fail("--saturating can't be used with --uint64");
+    }
+    // the interpreter works on unbounded integers, so it would give different results from the program compiled
+    if args.interpret && (args.uint64 || args.saturating || args.mod256) {
+        fail("--interpret can't be used with --uint64, --saturating or --mod256, since it doesn't limit the size of values");
+    }
+    let arithmetic = match (args.saturating, args.mod256) {
+        (false, false) => gen::Arithmetic::Wrapping,
+        (true, false) => gen::Arithmetic::Saturating,
+        (false, true) => gen::Arithmetic::Mod256,
+        (true, true) => fail("--saturating and --mod256 can't be used together"),
+    };
+    let format = match (args.output_format, args.csv) {
+        (format, false) => format,
+        (OutputFormat::Lines | OutputFormat::Csv, true) => OutputFormat::Csv,
+        (_, true) => fail("--csv can't be used with another --output-format"),
+    };
+    if args.no_output && (args.top_only || args.csv || args.space_separated || args.flush || args.hex || args.output_format != OutputFormat::Lines) {
+        fail("--no-output can't be used with another output option");
+    }
+    let output = match (format, args.top_only) {
+        _ if args.no_output => gen::Output::None,
+        (OutputFormat::Lines | OutputFormat::Csv, false) => gen::Output::Stack,
+        (OutputFormat::Lines, true) => gen::Output::Top,
+        (_, true) => fail("--top-only can only be used with the default output format"),
+        (OutputFormat::Json, false) => gen::Output::Json,
+    };
+    let hex = match (args.hex, format) {
+        (false, _) if args.hex_prefix || args.hex_negatives.is_some() => fail("--hex-prefix and --hex-negatives can only be used with --hex"),
+        (false, _) => None,
+        // JSON has no way to write a number in hexadecimal
+        (true, OutputFormat::Json) => fail("--hex can't be used with json output"),
+        (true, _) => Some(gen::Hex { prefix: args.hex_prefix, negatives: args.hex_negatives.unwrap_or(gen::HexNegatives::Sign) }),
+    };
+    let (separator, trailing_newline) = match (format, args.space_separated) {
+        (OutputFormat::Csv, true) => fail("--space-separated can't be used with csv output"),
+        // a csv line always ends in a newline, even when it has no values
+        (OutputFormat::Csv, false) => (String::from(","), gen::TrailingNewline::Always),
+        (_, true) => (String::from(" "), gen::TrailingNewline::Never),
+        (_, false) => (args.separator.clone(), gen::TrailingNewline::IfNotEmpty),
+    };
+    let mut passes = match &args.opt_passes {
+        Some(list) => list.split(',').filter(|p| !p.is_empty()).map(|p| p.parse().unwrap_or_else(|e: String| fail(&e))).collect(),
+        None => ast::Pass::ALL.to_vec(),
+    };
+    // hoisting twice would lose what the first one hoisted, and nothing gains from running a pass again
+    for (i, pass) in passes.iter().enumerate() {
+        if passes[..i].contains(pass) {
+            fail(&format!("pass `{}` is listed more than once in --opt-passes", pass));
+        }
+    }
+    passes.retain(|p| !args.fno.contains(p));
+    // a stack grows by doubling, which an empty one never would
+    let off_capacity = args.off_capacity.unwrap_or(1024);
+    if off_capacity == 0 {
+        fail("--off-capacity must be at least 1");
+    }
+    gen::Options {
+        input,
+        baked,
+        eof: args.eof,
+        output,
+        separator,
+        trailing_newline,
+        hex,
+        loop_limit: args.loop_limit,
+        c_standard: args.c_standard,
+        int_type: if args.uint64 { gen::IntType::U64 } else { gen::IntType::I64 },
+        arithmetic,
+        flush: args.flush,
+        debug_checks: args.debug_checks,
+        zero_init: args.zero_init,
+        off_capacity,
+        dump_on_interrupt: args.dump_on_interrupt,
+        function: args.emit_function.clone(),
+        passes,
+        max_depth: None,
+    }
+}
+
+// splits a list of flags the way a shell would, so that a flag can contain spaces if it's quoted or escaped
+fn split_flags(s: &str) -> Result<Vec<String>, String> {
+    let mut flags = Vec::new();
+    let mut cur: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                let cur = cur.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(d) if d == c => break,
+                        // only double quotes have escapes inside them
+                        Some('\\') if c == '"' => cur.extend(chars.next()),
+                        Some(d) => cur.push(d),
+                        None => return Err(format!("unclosed {} in FLAKC_FLAGS", c)),
+                    }
+                }
+            },
+            '\\' => cur.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() => flags.extend(cur.take()),
+            c => cur.get_or_insert_with(String::new).push(c),
+        }
+    }
+    flags.extend(cur);
+    Ok(flags)
+}
+
+// the C compiler that turns the generated C into the output, and the flags it's given before the file names; --cc
+// and --cflag take precedence over FLAKC_CC and FLAKC_FLAGS, which take precedence over the defaults
+fn compiler(args: &Args, emit: Emit) -> Option<(String, Vec<String>)> {
+    let (default_cc, flags) = emit.compiler()?;
+    let mut flags: Vec<String> = std::iter::once("-O2").chain(flags.iter().copied()).map(String::from).collect();
+    let chosen_cc = args.cc.clone().or_else(|| std::env::var("FLAKC_CC").ok().filter(|cc| !cc.is_empty()));
+    let cc = match (&args.target, chosen_cc) {
+        (None, cc) => cc.unwrap_or_else(|| String::from(default_cc)),
+        // a compiler that was asked for is trusted to take --target, and reports it if it doesn't
+        (Some(t), Some(cc)) => {
+            flags.push(format!("--target={}", t));
+            cc
+        },
+        (Some(t), None) if default_cc == "clang" => {
+            flags.push(format!("--target={}", t));
+            String::from(default_cc)
+        },
+        // gcc has no --target, since each gcc only builds for one target, so use the cross compiler named after it
+        (Some(t), None) => format!("{}-{}", t, default_cc),
+    };
+    if args.static_link {
+        flags.push(String::from("-static"));
+    }
+    if !args.cflag.is_empty() {
+        flags.extend(args.cflag.iter().cloned());
+    } else if let Ok(env_flags) = std::env::var("FLAKC_FLAGS") {
+        flags.extend(split_flags(&env_flags).unwrap_or_else(|e| fail(&e)));
+    }
+    Some((cc, flags))
+}
+
+// prints the settings that are in effect once the options and environment have been taken into account
+fn print_config(args: &Args, emit: Emit, opts: &gen::Options) {
+    match compiler(args, emit) {
+        Some((cc, flags)) => println!("compiler      {} {}", cc, flags.join(" ")),
+        None => println!("compiler      none"),
+    }
+    println!("emit          {}", match emit {
+        Emit::Tokens => "tokens",
+        Emit::Ast => "ast",
+        Emit::Ir => "ir",
+        Emit::CfgDot => "cfg-dot",
+        Emit::C => "c",
+        Emit::Asm => "asm",
+        Emit::Llvm => "llvm",
+        Emit::Obj => "obj",
+        Emit::Bin => "bin",
+        Emit::Runtime => "runtime",
+    });
+    println!("c standard    {}", match opts.c_standard {
+        gen::CStandard::C89 => "c89",
+        gen::CStandard::C99 => "c99",
+    });
+    println!("int type      {}", match opts.int_type {
+        gen::IntType::I64 => "i64",
+        gen::IntType::U64 => "u64",
+    });
+    println!("arithmetic    {}", match opts.arithmetic {
+        gen::Arithmetic::Wrapping => "wrapping",
+        gen::Arithmetic::Saturating => "saturating",
+        gen::Arithmetic::Mod256 => "mod256",
+    });
+    println!("input         {}", match opts.input {
+        gen::Input::Args => "args",
+        gen::Input::StdinNumbers => "stdin-numbers",
+        gen::Input::StdinChars => "stdin-chars",
+        gen::Input::Line => "line",
+        gen::Input::Baked => "baked",
+    });
+    println!("output        {}", match opts.output {
+        gen::Output::Stack => "stack",
+        gen::Output::Top => "top",
+        gen::Output::Json => "json",
+        gen::Output::None => "none",
+    });
+    println!("separator     {:?}", opts.separator);
+    match opts.hex {
+        Some(gen::Hex { prefix, negatives }) => println!("hex           {}{}", if prefix { "0x, " } else { "" }, match negatives {
+            gen::HexNegatives::Sign => "sign",
+            gen::HexNegatives::TwosComplement => "twos-complement",
+        }),
+        None => println!("hex           off"),
+    }
+    match opts.loop_limit {
+        Some(limit) => println!("loop limit    {}", limit),
+        None => println!("loop limit    none"),
+    }
+    println!("debug checks  {}", if opts.debug_checks { "on" } else { "off" });
+    println!("zero init     {}", if opts.zero_init { "on" } else { "off" });
+    println!("off capacity  {}", opts.off_capacity);
+    println!("interrupts    {}", if opts.dump_on_interrupt { "dump stack" } else { "default" });
+    match &opts.passes[..] {
+        [] => println!("passes        none"),
+        passes => println!("passes        {}", passes.iter().map(ast::Pass::to_string).collect::<Vec<_>>().join(",")),
+    }
+    println!("dialect       {}", match args.dialect {
+        parser::Dialect::BrainFlak => "brain-flak",
+        parser::Dialect::Flakcents => "flakcents",
+    });
+    println!("color         {}", if flakc::color::enabled() { "on" } else { "off" });
+    println!("cache         {}", args.cache_dir.as_deref().unwrap_or("none"));
+}
+
+fn write_header(args: &Args, opts: &gen::Options) -> Result<(), String> {
+    if let (Some(path), Some(name)) = (&args.emit_header, &opts.function) {
+        let header = fs::File::create(path).and_then(|mut h| gen::write_header(&mut h, name, opts.int_type, opts.c_standard));
+        header.map_err(|e| format!("couldn't write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+// where the output for this source would be in the cache, if there is one; everything that can change the output is
+// part of its name, including the version of flakc and the C compiler and flags it would be built with
+fn cache_entry(args: &Args, opts: &gen::Options, emit: Emit, source: &str) -> Option<std::path::PathBuf> {
+    let dir = args.cache_dir.as_ref()?;
+    // how diagnostics look doesn't change the output
+    let parser_opts = parser::Options { diagnostic_format: parser::DiagnosticFormat::Human, ..parser_options(args) };
+    let settings = format!("{:?} {:?} {:?}", opts, parser_opts, emit);
+    let key = hash_of(&[env!("CARGO_PKG_VERSION"), source, &settings, &format!("{:?}", compiler(args, emit))]);
+    Some(std::path::Path::new(dir).join(format!("{:016x}{}", key, emit.extension())))
+}
+
+// copies a new output into the cache, evicting the oldest entries once there are too many
+fn store_in_cache(entry: &std::path::Path, output: &str) -> std::io::Result<()> {
+    let dir = entry.parent().expect("cache entries are in the cache directory");
+    fs::create_dir_all(dir)?;
+    fs::copy(output, entry)?;
+    let mut entries: Vec<_> = fs::read_dir(dir)?.filter_map(|e| {
+        let e = e.ok()?;
+        Some((e.metadata().ok()?.modified().ok()?, e.path()))
+    }).collect();
+    if entries.len() > CACHE_SIZE {
+        entries.sort();
+        for (_, path) in &entries[..entries.len()-CACHE_SIZE] {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+const CACHE_SIZE: usize = 256;
+
+// turns a translated program into the output asked for, writing anything the C compiler prints to err
+fn build(args: &Args, opts: &gen::Options, emit: Emit, code: ast::Expr, source: &str, output: &str, err: &mut impl Write) -> Result<(), String> {
+    // named after the source and output so that compiling several programs in the same directory at once doesn't mix
+    // them up
+    let c_name = if emit == Emit::C { String::from(output) } else { format!(".flakc-{:016x}.c", hash_of(&[source, output])) };
+    let write_error = |e: std::io::Error| format!("couldn't write {}: {}", c_name, e);
+    // the generated C is written a few characters at a time
+    let mut c = std::io::BufWriter::new(fs::File::create(&c_name).map_err(write_error)?);
+    gen::compile(&mut c, code, opts).map_err(write_error)?;
+    c.flush().map_err(write_error)?;
+    write_header(args, opts)?;
+
+    if let Some((cc, flags)) = compiler(args, emit) {
+        let mut command = std::process::Command::new(&cc);
+        command.args(flags).args([&c_name, "-o", output]);
+        let result = command.output();
+        if !args.keep_temps {
+            let _ = fs::remove_file(&c_name);
+        }
+        let result = match (result, &args.target) {
+            (Ok(result), _) => result,
+            (Err(e), Some(t)) if e.kind() == std::io::ErrorKind::NotFound => return Err(format!("couldn't find {}, which is needed to compile for {}", cc, t)),
+            (Err(e), _) => return Err(format!("couldn't run {}: {}", cc, e)),
+        };
+        let _ = err.write_all(&result.stdout).and_then(|_| err.write_all(&result.stderr));
+        if !result.status.success() {
+            return Err(format!("{} failed to compile the generated C", cc));
+        }
+    }
+    Ok(())
+}
+
+// compiles one of several inputs to an output named after it, writing its diagnostics to err
+fn compile_file(args: &Args, opts: &gen::Options, emit: Emit, path: &str, err: &mut Vec<u8>) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+    let stem = std::path::Path::new(path).with_extension("");
+    let mut output = format!("{}{}", stem.display(), emit.extension());
+    // a binary compiled from a file with no extension would otherwise overwrite it
+    if output == path {
+        output.push_str(".out");
+    }
+    let mut parser = parser::Parser::new(&source, parser_options(args)).file(path);
+    let tree = parser.parse();
+    parser.report_to(err).expect("writing to a Vec can't fail");
+    let Some(tree) = tree else { return Err(format!("{} wasn't compiled because of the errors above", path)) };
+    let mut lints = Vec::new();
+    let code = ast::translate_with(tree, &mut lints, &opts.passes);
+    let ok = parser.lint(&lints);
+    parser.report_to(err).expect("writing to a Vec can't fail");
+    if !ok {
+        return Err(format!("{} wasn't compiled because of the errors above", path));
+    }
+    // only checked once the program has been parsed and linted, so a cached build still reports its diagnostics
+    let cache_entry = cache_entry(args, opts, emit, &source);
+    if let Some(entry) = &cache_entry {
+        if fs::copy(entry, &output).is_ok() {
+            return write_header(args, opts);
+        }
+    }
+    build(args, opts, emit, code, &source, &output, err)?;
+    if let Some(entry) = &cache_entry {
+        let _ = store_in_cache(entry, &output);
+    }
+    Ok(())
+}
+
+// compiles several inputs at once, one per thread, printing the diagnostics for each file together
+fn compile_all(args: &Args, emit: Emit, inputs: &[String]) -> std::io::Result<()> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    if args.output.is_some() {
+        fail("-o can't be used with several input files, since each output is named after its input");
+    }
+    if args.interpret || args.stats || args.analyze || args.emit_header.is_some() || emit.compiler().is_none() && emit != Emit::C {
+        fail("several input files can only be compiled to C or by a C compiler");
+    }
+    let opts = gen_options(args);
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(inputs.len());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some(path) = inputs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let mut err = Vec::new();
+                    if let Err(e) = compile_file(args, &opts, emit, path, &mut err) {
+                        let _ = writeln!(err, "{}: {}", "error".red().bold(), e);
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    // written all at once so that files finishing at the same time don't interleave their diagnostics
+                    let _ = std::io::stderr().lock().write_all(&err);
+                }
+            });
+        }
+    });
+    if failed.load(Ordering::Relaxed) {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
-    let args: Args = argh::from_env();
+    let args = parse_args();
 
-    let c_name = if args.output_c { &args.output } else { ".tmp.c" };
-    let mut output = fs::File::create(c_name)?;
+    if let Some(code) = args.explain {
+        let Some(text) = explain::explain(&code) else {
+            fail(&format!("no diagnostic with code `{}`", code));
+        };
+        print!("{}", text);
+        return Ok(());
+    }
 
-    let input = fs::read_to_string(args.input)?;
-    let Some(tree) = parser::parse(&input) else { return Ok(()) };
-    let code = ast::translate(tree);
+    if let Some(n) = args.encode {
+        println!("{}", encode::encode(&n));
+        return Ok(());
+    }
 
-    gen::compile(&mut output, code)?;
+    let emit = if args.output_c { Emit::C } else { args.emit };
+    if args.static_link && emit != Emit::Bin {
+        fail("--static can only be used when compiling to a binary");
+    }
+    if args.emit_function.is_some() && emit == Emit::Bin {
+        fail("--emit-function has no main to link into a binary, so it needs --emit c, asm, llvm or obj");
+    }
+    if args.emit_header.is_some() && args.emit_function.is_none() {
+        fail("--emit-header can only be used with --emit-function");
+    }
 
-    if !args.output_c {
-        std::process::Command::new("gcc")
-            .args(["-O2", ".tmp.c", "-o", &args.output])
-            .spawn()?
-            .wait()?;
+    if args.print_config {
+        print_config(&args, emit, &gen_options(&args));
+        return Ok(());
     }
 
+    if emit == Emit::Runtime {
+        let output = args.output.as_deref().unwrap_or("a.out");
+        return gen::write_runtime(&mut fs::File::create(output)?, &gen_options(&args));
+    }
+
+    let input = match &args.input[..] {
+        [] => fail("no input file"),
+        [input] => input,
+        inputs => return compile_all(&args, emit, inputs),
+    };
+    let path = input;
+    let input = fs::read_to_string(path)?;
+    let output = args.output.as_deref().unwrap_or("a.out");
+    let opts = gen_options(&args);
+    let mut parser = parser::Parser::new(&input, parser_options(&args)).file(path);
+    if emit == Emit::Tokens {
+        let mut output = fs::File::create(output)?;
+        parser.write_tokens(&mut output)?;
+        parser.report();
+        return Ok(());
+    }
+
+    let tree = parser.parse();
+    parser.report();
+    // exiting with an error like compile_all does, so that scripts can tell nothing was compiled
+    let Some(tree) = tree else { std::process::exit(1) };
+
+    if args.interpret {
+        let values = match &args.input_file {
+            Some(path) => interp::read_input_file(path),
+            None => interp::read_input(opts.input, &opts.baked, opts.eof),
+        }.unwrap_or_else(|e| fail(&e));
+        let interp_opts = interp::Options { step_limit: args.step_limit, loop_limit: args.loop_limit, trace: args.trace };
+        let (active, inactive) = interp::run_full(&tree, &values, &interp_opts).unwrap_or_else(|e| fail(&e.to_string()));
+        if opts.output == gen::Output::None {
+            return Ok(());
+        }
+        let mut out: Box<dyn Write> = match &args.run_output {
+            Some(path) => Box::new(std::io::BufWriter::new(fs::File::create(path)?)),
+            None => Box::new(std::io::stdout().lock()),
+        };
+        interp::write_output(&mut out, &active, &opts)?;
+        if args.dump_both {
+            writeln!(out)?;
+            interp::write_output(&mut out, &inactive, &opts)?;
+        }
+        return out.flush();
+    }
+
+    if emit == Emit::Ast {
+        let mut output = fs::File::create(output)?;
+        return ast::write_json(&mut output, &tree, args.pretty);
+    }
+
+    let mut stats = stats::ast_stats(&tree);
+    let mut lints = Vec::new();
+    let code = ast::translate_with(tree, &mut lints, &opts.passes);
+    let ok = parser.lint(&lints);
+    parser.report();
+    if !ok {
+        std::process::exit(1);
+    }
+
+    match emit {
+        Emit::Ir => return ast::write_ir(&mut fs::File::create(output)?, &code),
+        Emit::CfgDot => return dot::write_dot(&mut fs::File::create(output)?, code),
+        _ => {},
+    }
+
+    if args.stats {
+        stats.effects = stats::count_effects(&code.effects);
+        stats::print(&stats);
+        return Ok(());
+    }
+
+    if args.analyze {
+        analyze::print(&analyze::analyze(&code));
+        return Ok(());
+    }
+
+    // only checked once the program has been parsed and linted, so a cached build still reports its diagnostics
+    let cacheable = emit == Emit::C || emit.compiler().is_some();
+    let cache_entry = cache_entry(&args, &opts, emit, &input).filter(|_| cacheable);
+    if let Some(entry) = &cache_entry {
+        if fs::copy(entry, output).is_ok() {
+            write_header(&args, &opts).unwrap_or_else(|e| fail(&e));
+            return Ok(());
+        }
+    }
+
+    build(&args, &opts, emit, code, &input, output, &mut std::io::stderr()).unwrap_or_else(|e| fail(&e));
+    if let Some(entry) = &cache_entry {
+        // a cache that can't be written to only makes later compiles slower
+        let _ = store_in_cache(entry, output);
+    }
     Ok(())
 }
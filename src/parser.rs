@@ -1,7 +1,9 @@
-use colored::Colorize;
-use crate::ast::{Ast, Inst::{*}};
+use crate::color::Colorize;
+use std::io::Write;
+use crate::ast::{Ast, Node, Lint, Inst::{*}};
 
-fn show_span(s: &str, pos: usize) {
+// returns the line and column that pos is at, along with the text of that line
+fn find_line(s: &str, pos: usize) -> (usize, usize, String) {
     let mut line = 1;
     let mut column = 1;
     let mut cur_line = String::new();
@@ -21,15 +23,301 @@ fn show_span(s: &str, pos: usize) {
             cur_line.push(c);
         }
     }
-    eprintln!(" {} :{}:{}", "-->".blue(), line, column);
-    eprintln!("{}", "     |".blue());
-    eprintln!("{:>4} {} {}", line.to_string().blue(), "|".blue(), cur_line);
-    eprintln!("{} {: <3$}{}", "     |".blue(), "", "~".red(), column-1);
+    (line, column, cur_line)
 }
 
-fn report(s: &str, level: &'static str, msg: &'static str, pos: usize) {
-    eprintln!("{}: {}", level.red().bold(), msg);
-    show_span(s, pos);
+// how many terminal columns come before the given column of a line, since full-width characters take up two
+fn indent(line: &str, column: usize) -> usize {
+    line.chars().take(column-1).map(|c| if is_full_width(c) { 2 } else { 1 }).sum()
+}
+
+fn is_full_width(c: char) -> bool {
+    matches!(c, '\u{1100}'..='\u{115F}' | '\u{2E80}'..='\u{A4CF}' | '\u{AC00}'..='\u{D7A3}' | '\u{F900}'..='\u{FAFF}' | '\u{FE30}'..='\u{FE4F}' | '\u{FF00}'..='\u{FF60}' | '\u{FFE0}'..='\u{FFE6}')
+}
+
+// underlines len characters starting at pos, with a ^ under the first and ~ under the rest
+fn show_span(b: &mut impl Write, s: &str, file: &str, pos: usize, len: usize) -> std::io::Result<()> {
+    let (line, column, cur_line) = find_line(s, pos);
+    let (end_line, end_column, last_line) = find_line(s, pos + len.max(1) - 1);
+    writeln!(b, " {} {}:{}:{}", "-->".blue(), file, line, column)?;
+    writeln!(b, "{}", "     |".blue())?;
+    writeln!(b, "{:>4} {} {}", line.to_string().blue(), "|".blue(), cur_line)?;
+    let start = indent(&cur_line, column);
+    // a span that ends on a later line is underlined to the end of its first line, then on its last line up to where it ends
+    let end = if end_line == line { end_column + 1 } else { cur_line.chars().count() + 1 };
+    let underline = format!("^{}", "~".repeat(indent(&cur_line, end).max(start + 1) - start - 1));
+    writeln!(b, "{} {: <3$}{}", "     |".blue(), "", underline.red(), start)?;
+    if end_line == line {
+        return Ok(());
+    }
+    if end_line > line + 1 {
+        writeln!(b, "{}", "...".blue())?;
+    }
+    writeln!(b, "{:>4} {} {}", end_line.to_string().blue(), "|".blue(), last_line)?;
+    let start = indent(&last_line, last_line.chars().take_while(|c| c.is_whitespace()).count() + 1);
+    let end = indent(&last_line, end_column + 1).max(start + 1);
+    writeln!(b, "{} {: <3$}{}", "     |".blue(), "", "~".repeat(end - start).red(), start)
+}
+
+// shows another place that's relevant to a diagnostic underneath its main span, with a label saying why
+fn show_secondary_span(b: &mut impl Write, s: &str, pos: usize, label: &str) -> std::io::Result<()> {
+    let (line, column, cur_line) = find_line(s, pos);
+    writeln!(b, "{}", "     |".blue())?;
+    writeln!(b, "{:>4} {} {}", line.to_string().blue(), "|".blue(), cur_line)?;
+    writeln!(b, "{} {: <4$}{} {}", "     |".blue(), "", "-".blue(), label.blue(), indent(&cur_line, column))
+}
+
+fn line_col(s: &str, pos: usize) -> (usize, usize) {
+    let before: Vec<char> = s.chars().take(pos).collect();
+    let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+    let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+// a change to the source, replacing len characters at the character offset pos
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub pos: usize,
+    pub len: usize,
+    pub replacement: String,
+}
+
+// a fix that a diagnostic suggests, made of edits that have to be applied together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub msg: String,
+    pub edits: Vec<Edit>,
+}
+
+// a diagnostic found in the source, which stops it from compiling if it's an error
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub level: Level,
+    pub code: &'static str,
+    pub msg: String,
+    // the character offset in the source that the diagnostic points at, and how many characters it covers from there
+    pub pos: usize,
+    pub len: usize,
+    // other offsets that help explain the diagnostic, each with a label saying what's there
+    pub secondary: Vec<(usize, String)>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+    // help that can be applied automatically
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl ParseError {
+    fn new(level: Level, code: &'static str, msg: &str, pos: usize) -> ParseError {
+        ParseError { level, code, msg: String::from(msg), pos, len: 1, secondary: Vec::new(), notes: Vec::new(), help: Vec::new(), suggestions: Vec::new() }
+    }
+
+    fn error(code: &'static str, msg: &str, pos: usize) -> ParseError {
+        ParseError::new(Level::Error, code, msg, pos)
+    }
+
+    fn warning(code: &'static str, msg: &str, pos: usize) -> ParseError {
+        ParseError::new(Level::Warning, code, msg, pos)
+    }
+
+    fn len(mut self, len: usize) -> ParseError {
+        self.len = len;
+        self
+    }
+
+    fn secondary(mut self, pos: usize, label: &str) -> ParseError {
+        self.secondary.push((pos, String::from(label)));
+        self
+    }
+
+    fn note(mut self, msg: &str) -> ParseError {
+        self.notes.push(String::from(msg));
+        self
+    }
+
+    fn help(mut self, msg: &str) -> ParseError {
+        self.help.push(String::from(msg));
+        self
+    }
+
+    fn suggest(mut self, msg: &str, edits: Vec<Edit>) -> ParseError {
+        self.suggestions.push(Suggestion { msg: String::from(msg), edits });
+        self
+    }
+}
+
+/// How diagnostics are written out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiagnosticFormat {
+    /// Several lines each, showing the source they point at, with notes and suggestions.
+    #[default]
+    Human,
+    /// One `file:line:col: level: message [code]` line each, like gcc and clang print.
+    Short,
+    /// One JSON object per line each, with every detail of the diagnostic.
+    Json,
+}
+
+impl std::str::FromStr for DiagnosticFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiagnosticFormat, String> {
+        match s {
+            "human" => Ok(DiagnosticFormat::Human),
+            "short" => Ok(DiagnosticFormat::Short),
+            "json" => Ok(DiagnosticFormat::Json),
+            _ => Err(format!("unknown diagnostic format `{}` (expected human, short or json)", s)),
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// writes a diagnostic in the given format, where file is the name the source is reported under
+pub fn write_report(b: &mut impl Write, s: &str, file: &str, e: &ParseError, format: DiagnosticFormat) -> std::io::Result<()> {
+    let level = match e.level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+    };
+    let (line, column) = line_col(s, e.pos);
+    let (end_line, end_column) = line_col(s, e.pos + e.len);
+    match format {
+        DiagnosticFormat::Human => {},
+        DiagnosticFormat::Short => return writeln!(b, "{}:{}:{}: {}: {} [{}]", file, line, column, level, e.msg, e.code),
+        DiagnosticFormat::Json => {
+            write!(b, "{{\"file\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{},\"level\":\"{}\",\"code\":\"{}\",\"message\":{},\"secondary\":[",
+                json_string(file), line, column, end_line, end_column, level, e.code, json_string(&e.msg))?;
+            for (i, (pos, label)) in e.secondary.iter().enumerate() {
+                let (line, column) = line_col(s, *pos);
+                write!(b, "{}{{\"line\":{},\"column\":{},\"label\":{}}}", if i > 0 { "," } else { "" }, line, column, json_string(label))?;
+            }
+            let list = |items: &[String]| items.iter().map(|i| json_string(i)).collect::<Vec<_>>().join(",");
+            write!(b, "],\"notes\":[{}],\"help\":[{}],\"suggestions\":[", list(&e.notes), list(&e.help))?;
+            for (i, suggestion) in e.suggestions.iter().enumerate() {
+                write!(b, "{}{{\"message\":{},\"edits\":[", if i > 0 { "," } else { "" }, json_string(&suggestion.msg))?;
+                for (j, edit) in suggestion.edits.iter().enumerate() {
+                    let (line, column) = line_col(s, edit.pos);
+                    let (end_line, end_column) = line_col(s, edit.pos + edit.len);
+                    write!(b, "{}{{\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{},\"replacement\":{}}}",
+                        if j > 0 { "," } else { "" }, line, column, end_line, end_column, json_string(&edit.replacement))?;
+                }
+                write!(b, "]}}")?;
+            }
+            return writeln!(b, "]}}");
+        },
+    }
+    writeln!(b, "{}: {}", format!("{}[{}]", level, e.code).red().bold(), e.msg)?;
+    show_span(b, s, file, e.pos, e.len)?;
+    for (pos, label) in &e.secondary {
+        show_secondary_span(b, s, *pos, label)?;
+    }
+    // notes and help are attached to the span above rather than standing on their own
+    for note in &e.notes {
+        writeln!(b, "{} {}: {}", "     =".blue(), "note".bold(), note)?;
+    }
+    for help in e.help.iter().chain(e.suggestions.iter().map(|s| &s.msg)) {
+        writeln!(b, "{} {}: {}", "     =".blue(), "help".green().bold(), help)?;
+    }
+    Ok(())
+}
+
+/// Which characters delimit instructions.
+///
+/// Flakcents writes each bracket as an accented vowel, with an acute accent opening and a grave accent closing:
+///
+/// | Brain-Flak | Flakcents |
+/// |------------|-----------|
+/// | `(` `)`    | `á` `à`   |
+/// | `{` `}`    | `é` `è`   |
+/// | `[` `]`    | `í` `ì`   |
+/// | `<` `>`    | `ó` `ò`   |
+///
+/// The ASCII brackets and their full-width forms (`（）｛｝［］＜＞`) keep working in every dialect, so a program can mix
+/// them.
+///
+/// ```
+/// use flakc::parser::{Parser, Options, Dialect};
+///
+/// let brain_flak = Parser::new("({}[()])", Options::default()).parse().unwrap();
+/// let flakcents = Parser::new("áéèíáàìà", Options { dialect: Dialect::Flakcents, ..Options::default() }).parse().unwrap();
+/// assert_eq!(format!("{:?}", brain_flak), format!("{:?}", flakcents));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    BrainFlak,
+    Flakcents,
+}
+
+impl Dialect {
+    // the ASCII bracket that a character stands for in this dialect, or the character itself if it isn't one
+    fn translate(self, c: char) -> char {
+        match (self, c) {
+            (Dialect::Flakcents, 'á') => '(',
+            (Dialect::Flakcents, 'à') => ')',
+            (Dialect::Flakcents, 'é') => '{',
+            (Dialect::Flakcents, 'è') => '}',
+            (Dialect::Flakcents, 'í') => '[',
+            (Dialect::Flakcents, 'ì') => ']',
+            (Dialect::Flakcents, 'ó') => '<',
+            (Dialect::Flakcents, 'ò') => '>',
+            // full-width brackets, which some editors insert in place of ASCII ones, work in every dialect
+            (_, '（') => '(',
+            (_, '）') => ')',
+            (_, '｛') => '{',
+            (_, '｝') => '}',
+            (_, '［') => '[',
+            (_, '］') => ']',
+            (_, '＜') => '<',
+            (_, '＞') => '>',
+            _ => c,
+        }
+    }
+}
+
+impl std::str::FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dialect, String> {
+        match s {
+            "brain-flak" => Ok(Dialect::BrainFlak),
+            "flakcents" => Ok(Dialect::Flakcents),
+            _ => Err(format!("unknown dialect `{}` (expected brain-flak or flakcents)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub strict: bool,
+    pub dialect: Dialect,
+    pub diagnostic_format: DiagnosticFormat,
+}
+
+impl Options {
+    fn has(list: &[String], code: &str) -> bool {
+        list.iter().any(|c| c.eq_ignore_ascii_case(code))
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -41,6 +329,26 @@ enum DelimType {
 }
 use DelimType::{*};
 
+impl DelimType {
+    fn open(self) -> char {
+        match self {
+            Paren => '(',
+            Brace => '{',
+            Bracket => '[',
+            Angle => '<',
+        }
+    }
+
+    fn close(self) -> char {
+        match self {
+            Paren => ')',
+            Brace => '}',
+            Bracket => ']',
+            Angle => '>',
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 enum TokenType {
     Open(DelimType),
@@ -55,137 +363,303 @@ struct Token {
     pos: usize,
 }
 
-fn lex(s: &str) -> Option<Vec<Token>> {
-    let mut ts = Vec::new();
-    let mut line_is_false_comment = false;
-    let mut line_is_comment = false;
-    let mut last_was_hash = false;
-    let mut block_comment_level: usize = 0;
-    for (pos, c) in s.chars().enumerate() {
-        if line_is_comment {
-            if last_was_hash && c == '{' {
-                line_is_comment = false;
-                block_comment_level = 1;
-            }
-            if c == '\n' {
-                line_is_comment = false;
-            }
-            last_was_hash = false;
-            continue;
-        }
-        if block_comment_level > 0 {
-            if c == '{' {
-                block_comment_level += 1;
-            } else if c == '}' {
-                block_comment_level -= 1;
-            }
-            continue;
+// parses one source file, collecting diagnostics along the way rather than printing them
+pub struct Parser<'a> {
+    s: &'a str,
+    // the name diagnostics give the source, which is empty unless it's set
+    file: String,
+    opts: Options,
+    diags: Vec<ParseError>,
+    // where the bracket closing each instruction is, by where its opening bracket is
+    closers: std::collections::HashMap<usize, usize>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(s: &'a str, opts: Options) -> Parser<'a> {
+        Parser { s, file: String::new(), opts, diags: Vec::new(), closers: std::collections::HashMap::new() }
+    }
+
+    // gives the name of the file the source came from, for diagnostics to point at
+    pub fn file(mut self, name: &str) -> Parser<'a> {
+        self.file = String::from(name);
+        self
+    }
+
+    // the diagnostics collected so far, in the order they were found
+    pub fn diagnostics(&self) -> &[ParseError] {
+        &self.diags
+    }
+
+    pub fn into_diagnostics(self) -> Vec<ParseError> {
+        self.diags
+    }
+
+    // prints the diagnostics collected so far and forgets them, so later stages only print their own
+    pub fn report(&mut self) {
+        // there's nowhere left to report a failure to write to stderr
+        let _ = self.report_to(&mut std::io::stderr().lock());
+    }
+
+    // like report, but prints them somewhere other than stderr
+    pub fn report_to(&mut self, b: &mut impl Write) -> std::io::Result<()> {
+        for e in self.diags.drain(..) {
+            write_report(b, self.s, &self.file, &e, self.opts.diagnostic_format)?;
         }
-        match c {
-            '(' => ts.push(Token { ty: Open(Paren), pos }),
-            ')' => ts.push(Token { ty: Close(Paren), pos }),
-            '{' => ts.push(Token { ty: Open(Brace), pos }),
-            '}' => ts.push(Token { ty: Close(Brace), pos }),
-            '[' => ts.push(Token { ty: Open(Bracket), pos }),
-            ']' => ts.push(Token { ty: Close(Bracket), pos }),
-            '<' => ts.push(Token { ty: Open(Angle), pos }),
-            '>' => ts.push(Token { ty: Close(Angle), pos }),
-            '#' => {
-                last_was_hash = true;
-                line_is_comment = true;
-            },
-            _ => {
+        Ok(())
+    }
+
+    // records a warning as a warning or an error depending on the options, returning None if it's allowed
+    fn warn(&mut self, mut e: ParseError) -> Option<bool> {
+        if Options::has(&self.opts.allow, e.code) {
+            return None;
+        }
+        let denied = Options::has(&self.opts.deny, e.code);
+        if denied {
+            e.level = Level::Error;
+        }
+        self.diags.push(e);
+        Some(denied)
+    }
+
+    fn lex(&mut self) -> Option<Vec<Token>> {
+        let mut ts = Vec::new();
+        let mut denied = false;
+        let mut line_is_false_comment = false;
+        let mut line_is_comment = false;
+        let mut last_was_hash = false;
+        // where the junk that makes the current line look like a comment starts, and where its last visible character ends
+        let mut junk_start = 0;
+        let mut junk_end = 0;
+        // where the #{ of the block comment being skipped and each unclosed { inside it are, outermost first
+        let mut block_comment_opens: Vec<usize> = Vec::new();
+        let mut chars = self.s.chars().enumerate().peekable();
+        while let Some((pos, c)) = chars.next() {
+            let c = self.opts.dialect.translate(c);
+            if line_is_comment {
+                if last_was_hash && c == '{' {
+                    line_is_comment = false;
+                    block_comment_opens.push(pos-1);
+                }
                 if c == '\n' {
-                    line_is_false_comment = false;
-                } else if !c.is_whitespace() {
-                    line_is_false_comment = true;
+                    line_is_comment = false;
                 }
-                if !matches!(ts.last(), Some(Token { ty: Junk, pos: _ })) {
-                    ts.push(Token { ty: Junk, pos });
+                last_was_hash = false;
+                continue;
+            }
+            if !block_comment_opens.is_empty() {
+                if c == '{' {
+                    block_comment_opens.push(pos);
+                } else if c == '}' {
+                    // }# ends the comment no matter how unbalanced the braces inside it are, but a } that balances
+                    // them ends it anyway, and then a # after it starts a line comment as it always has
+                    if block_comment_opens.len() > 1 && chars.next_if(|&(_, c)| c == '#').is_some() {
+                        block_comment_opens.clear();
+                    } else {
+                        block_comment_opens.pop();
+                    }
                 }
                 continue;
-            },
-        }
-        if line_is_false_comment {
-            line_is_false_comment = false;
-            report(s, "warning", "instructions appear after earlier junk characters on the same line", pos);
-            eprintln!("{}: this may be an unintentional inclusion of instructions in prose intended to be a comment", "note".bold());
-            eprintln!("{}: you can use # for a line comment", "help".green().bold());
-            eprintln!("{}: if this is intentional, consider using a #{{block comment}} to enclose the junk characters", "help".green().bold())
-        }
-    }
-    if block_comment_level > 0 {
-        report(s, "error", "unclosed block comment somewhere (don't ask where, this is just pointing at the start of the program)", 0);
-        return None;
-    }
-    Some(ts)
-}
-
-fn parse_tokens(ts: &mut &[Token], s: &str) -> Option<Ast> {
-    let mut a = Vec::new();
-
-    while !ts.is_empty() {
-        match ts[0].ty {
-            Open(t) => {
-                let nilad = if ts.len() >= 3 && ts[1].ty == Junk && ts[2].ty == Close(t) {
-                    report(s, "warning", "junk characters enclosed within nilad", ts[1].pos);
-                    eprintln!("{}: this harms readability by making it less clear that this is a nilad", "note".bold());
-                    *ts = &ts[3..];
-                    true
-                } else if ts.len() >= 2 && ts[1].ty == Close(t) {
-                    *ts = &ts[2..];
-                    true
-                } else {
-                    false
-                };
-                if nilad {
-                    a.push(match t {
-                        Paren => One,
-                        Brace => Pop,
-                        Bracket => Size,
-                        Angle => Toggle,
-                    });
-                } else {
-                    let prev_pos = ts[0].pos;
-                    *ts = &ts[1..];
-                    let ast = parse_tokens(ts, s)?;
-                    if ts.is_empty() {
-                        report(s, "error", "unclosed delimiter", prev_pos);
+            }
+            match c {
+                '(' => ts.push(Token { ty: Open(Paren), pos }),
+                ')' => ts.push(Token { ty: Close(Paren), pos }),
+                '{' => ts.push(Token { ty: Open(Brace), pos }),
+                '}' => ts.push(Token { ty: Close(Brace), pos }),
+                '[' => ts.push(Token { ty: Open(Bracket), pos }),
+                ']' => ts.push(Token { ty: Close(Bracket), pos }),
+                '<' => ts.push(Token { ty: Open(Angle), pos }),
+                '>' => ts.push(Token { ty: Close(Angle), pos }),
+                '#' => {
+                    last_was_hash = true;
+                    line_is_comment = true;
+                },
+                _ => {
+                    if self.opts.strict && !c.is_whitespace() {
+                        self.diags.push(ParseError::error("E0005", "junk character outside of a comment", pos)
+                            .help("use # for a line comment or #{block comment} to include prose"));
                         return None;
                     }
-                    let post_pos = ts[0].pos;
-                    let (attempt, len) = if ts[0].ty == Junk {
-                        (ts[1].ty, 2)
+                    if c == '\n' {
+                        line_is_false_comment = false;
+                    } else if !c.is_whitespace() {
+                        if !line_is_false_comment {
+                            junk_start = pos;
+                        }
+                        junk_end = pos + 1;
+                        line_is_false_comment = true;
+                    }
+                    if !matches!(ts.last(), Some(Token { ty: Junk, pos: _ })) {
+                        ts.push(Token { ty: Junk, pos });
+                    }
+                    continue;
+                },
+            }
+            if line_is_false_comment {
+                line_is_false_comment = false;
+                let e = ParseError::warning("W0001", "instructions appear after earlier junk characters on the same line", pos)
+                    .note("this may be an unintentional inclusion of instructions in prose intended to be a comment")
+                    .suggest("you can use # for a line comment", vec![Edit { pos: junk_start, len: 0, replacement: String::from("#") }])
+                    .suggest("if this is intentional, consider using a #{block comment} to enclose the junk characters", vec![
+                        Edit { pos: junk_start, len: 0, replacement: String::from("#{") },
+                        Edit { pos: junk_end, len: 0, replacement: String::from("}") },
+                    ]);
+                if let Some(d) = self.warn(e) {
+                    denied |= d;
+                }
+            }
+        }
+        match block_comment_opens[..] {
+            [] => {},
+            [start] => {
+                self.diags.push(ParseError::error("E0001", "unclosed block comment", start)
+                    .help("close it with a } that balances the braces inside it, or with }#"));
+                return None;
+            },
+            [start, .., innermost] => {
+                self.diags.push(ParseError::error("E0001", "unclosed brace inside a block comment", innermost)
+                    .secondary(start, "the block comment starts here")
+                    .note("a block comment doesn't end until every brace inside it is closed")
+                    .help("close the brace, or end the comment with }# to ignore the braces inside it"));
+                return None;
+            },
+        }
+        if denied {
+            return None;
+        }
+        Some(ts)
+    }
+
+    fn parse_tokens(&mut self, ts: &mut &[Token]) -> Option<Ast> {
+        let mut a = Ast::default();
+
+        while !ts.is_empty() {
+            match ts[0].ty {
+                Open(t) => {
+                    let pos = ts[0].pos;
+                    let nilad = if ts.len() >= 3 && ts[1].ty == Junk && ts[2].ty == Close(t) {
+                        // the junk token runs up to the closing delimiter, but only its visible characters are underlined
+                        let junk: Vec<char> = self.s.chars().skip(ts[1].pos).take(ts[2].pos - ts[1].pos).collect();
+                        let start = junk.iter().take_while(|c| c.is_whitespace()).count();
+                        let end = (junk.len() - junk.iter().rev().take_while(|c| c.is_whitespace()).count()).max(start);
+                        let e = ParseError::warning("W0002", "junk characters enclosed within nilad", ts[1].pos + start)
+                            .len((end - start).max(1))
+                            .note("this harms readability by making it less clear that this is a nilad");
+                        if self.warn(e) == Some(true) {
+                            return None;
+                        }
+                        self.closers.insert(pos, ts[2].pos);
+                        *ts = &ts[3..];
+                        true
+                    } else if ts.len() >= 2 && ts[1].ty == Close(t) {
+                        self.closers.insert(pos, ts[1].pos);
+                        *ts = &ts[2..];
+                        true
                     } else {
-                        (ts[0].ty, 1)
+                        false
                     };
-                    if attempt == Close(t) {
-                        *ts = &ts[len..];
+                    if nilad {
+                        a.push(Node { inst: match t {
+                            Paren => One,
+                            Brace => Pop,
+                            Bracket => Size,
+                            Angle => Toggle,
+                        }, pos });
                     } else {
-                        report(s, "error", "incorrect closing delimiter", post_pos+len-1);
-                        return None;
+                        let prev_pos = ts[0].pos;
+                        *ts = &ts[1..];
+                        let ast = self.parse_tokens(ts)?;
+                        if ts.is_empty() {
+                            self.diags.push(ParseError::error("E0002", "unclosed delimiter", prev_pos));
+                            return None;
+                        }
+                        let post_pos = ts[0].pos;
+                        let (attempt, len) = if ts[0].ty == Junk {
+                            (ts[1].ty, 2)
+                        } else {
+                            (ts[0].ty, 1)
+                        };
+                        let found = match attempt {
+                            Close(f) if f == t => {
+                                self.closers.insert(prev_pos, ts[len-1].pos);
+                                *ts = &ts[len..];
+                                None
+                            },
+                            Close(f) => Some(f.close()),
+                            Open(f) => Some(f.open()),
+                            Junk => unreachable!(),
+                        };
+                        if let Some(found) = found {
+                            let msg = format!("incorrect closing delimiter: expected `{}` to close `{}`, found `{}`", t.close(), t.open(), found);
+                            self.diags.push(ParseError::error("E0003", &msg, post_pos+len-1)
+                                .secondary(prev_pos, &format!("this `{}` is opened here", t.open())));
+                            return None;
+                        }
+                        a.push(Node { inst: match t {
+                            Paren => Push(ast),
+                            Brace => Loop(ast),
+                            Bracket => Negate(ast),
+                            Angle => Exec(ast),
+                        }, pos: prev_pos })
                     }
-                    a.push(match t {
-                        Paren => Push(ast),
-                        Brace => Loop(ast),
-                        Bracket => Negate(ast),
-                        Angle => Exec(ast),
-                    })
-                }
-            },
-            Close(_) => break,
-            Junk => *ts = &ts[1..],
+                },
+                Close(_) => break,
+                Junk => *ts = &ts[1..],
+            }
         }
+        Some(a)
+    }
+
+    // records the lints found during translation, returning false if any of them were denied
+    pub fn lint(&mut self, lints: &[Lint]) -> bool {
+        let mut ok = true;
+        for l in lints {
+            let e = match l.code {
+                "W0003" => ParseError::warning(l.code, "push of a value that is always zero", l.pos)
+                    .note("this only grows the stack by a zero, which may not be what was intended")
+                    .help("if the zero is intended, write it as (<...>) to make that clear"),
+                "W0004" => ParseError::warning(l.code, "toggle immediately undone by another toggle", l.pos)
+                    .help("<><> switches to the other stack and straight back, so both can be removed"),
+                "W0005" => ParseError::warning(l.code, "value is computed and then discarded", l.pos)
+                    .note("nothing reads this value and computing it has no effect on the stacks")
+                    .help("remove it, or wrap the expression it belongs to in (...) to push it"),
+                _ => unreachable!(),
+            };
+            // the whole of what it covers is underlined, from its first opening bracket to its last closing one
+            let end = self.closers.get(&l.last).copied().unwrap_or(l.last);
+            ok &= self.warn(e.len(end + 1 - l.pos)) != Some(true);
+        }
+        ok
     }
-    Some(a)
-}
 
-pub fn parse(s: &str) -> Option<Ast> {
-    let mut token_slice = &*lex(s)?;
-    let r = parse_tokens(&mut token_slice, s)?;
-    if !token_slice.is_empty() {
-        report(s, "error", "unexpected closing delimiter", token_slice[0].pos);
-        return None;
+    // writes each token on its own line with its position, returning false if lexing failed
+    pub fn write_tokens(&mut self, b: &mut impl Write) -> std::io::Result<bool> {
+        let Some(ts) = self.lex() else { return Ok(false) };
+        for t in ts {
+            let (line, column) = line_col(self.s, t.pos);
+            let text = match t.ty {
+                Open(Paren) => "(",
+                Close(Paren) => ")",
+                Open(Brace) => "{",
+                Close(Brace) => "}",
+                Open(Bracket) => "[",
+                Close(Bracket) => "]",
+                Open(Angle) => "<",
+                Close(Angle) => ">",
+                Junk => "junk",
+            };
+            writeln!(b, "{}:{} {}", line, column, text)?;
+        }
+        Ok(true)
+    }
+
+    // any warnings are collected too, so they can be reported whether or not parsing succeeds
+    pub fn parse(&mut self) -> Option<Ast> {
+        let mut token_slice = &*self.lex()?;
+        let r = self.parse_tokens(&mut token_slice)?;
+        if !token_slice.is_empty() {
+            self.diags.push(ParseError::error("E0004", "unexpected closing delimiter", token_slice[0].pos));
+            return None;
+        }
+        Some(r)
     }
-    Some(r)
 }
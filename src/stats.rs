@@ -0,0 +1,105 @@
+use crate::ast::{self, Ast, Node, Inst, Effect, Effects, Visit};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub one: usize,
+    pub size: usize,
+    pub pop: usize,
+    pub toggle: usize,
+    pub push: usize,
+    pub negate: usize,
+    pub loops: usize,
+    pub exec: usize,
+    pub max_depth: usize,
+    pub effects: usize,
+}
+
+impl Stats {
+    pub fn instructions(&self) -> usize {
+        self.one + self.size + self.pop + self.toggle + self.push + self.negate + self.loops + self.exec
+    }
+}
+
+// counts the instructions it visits, keeping track of how deeply nested the current one is
+struct Counter {
+    stats: Stats,
+    depth: usize,
+}
+
+impl Visit for Counter {
+    fn visit_node(&mut self, node: &Node) {
+        let stats = &mut self.stats;
+        match node.inst {
+            Inst::One => stats.one += 1,
+            Inst::Size => stats.size += 1,
+            Inst::Pop => stats.pop += 1,
+            Inst::Toggle => stats.toggle += 1,
+            Inst::Push(_) => stats.push += 1,
+            Inst::Negate(_) => stats.negate += 1,
+            Inst::Loop(_) => stats.loops += 1,
+            Inst::Exec(_) => stats.exec += 1,
+        }
+        if node.inst.body().is_some() {
+            self.depth += 1;
+            self.stats.max_depth = self.stats.max_depth.max(self.depth);
+            ast::walk_node(self, node);
+            self.depth -= 1;
+        }
+    }
+}
+
+pub fn count_effects(e: &Effects) -> usize {
+    e.iter().map(|effect| match effect {
+        Effect::Stack(_) => 1,
+        Effect::Loop(e) => 1 + count_effects(&e.effects),
+        Effect::Countdown(_) | Effect::Drain(_) | Effect::Transfer(_) | Effect::Sum(_) => 1,
+    }).sum()
+}
+
+pub fn ast_stats(ast: &Ast) -> Stats {
+    let mut counter = Counter { stats: Stats::default(), depth: 0 };
+    ast::walk_ast(&mut counter, ast);
+    counter.stats
+}
+
+/// Counts every instruction in a tree, including the ones nested inside others.
+///
+/// ```
+/// let ast: flakc::Ast = "({}[()])".parse().unwrap();
+/// assert_eq!(flakc::count_instructions(&ast), 4);
+/// ```
+pub fn count_instructions(ast: &Ast) -> usize {
+    ast::fold_ast(ast, 0, |n, _| n + 1)
+}
+
+/// Counts the instructions in a tree by kind, keyed by the name of their [`Inst`] variant, with every kind present
+/// even if it doesn't appear.
+pub fn count_instructions_by_kind(ast: &Ast) -> HashMap<&'static str, usize> {
+    let stats = ast_stats(ast);
+    HashMap::from([
+        ("One", stats.one),
+        ("Size", stats.size),
+        ("Pop", stats.pop),
+        ("Toggle", stats.toggle),
+        ("Push", stats.push),
+        ("Negate", stats.negate),
+        ("Loop", stats.loops),
+        ("Exec", stats.exec),
+    ])
+}
+
+pub fn print(stats: &Stats) {
+    println!("instructions  {}", stats.instructions());
+    println!("  ()          {}", stats.one);
+    println!("  []          {}", stats.size);
+    println!("  {{}}          {}", stats.pop);
+    println!("  <>          {}", stats.toggle);
+    println!("  (...)       {}", stats.push);
+    println!("  [...]       {}", stats.negate);
+    println!("  {{...}}       {}", stats.loops);
+    println!("  <...>       {}", stats.exec);
+    println!("max depth     {}", stats.max_depth);
+    println!("loops         {}", stats.loops);
+    println!("effects       {}", stats.effects);
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// a directory of its own for each test, so they can run at the same time
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("flakc-cli-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// runs flakc in dir on a file holding src, with the given arguments after the file's name
+fn flakc(dir: &PathBuf, src: &str, args: &[&str]) -> Output {
+    std::fs::write(dir.join("prog.bf"), src).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_flakc")).current_dir(dir).arg("prog.bf").args(args).output().unwrap()
+}
+
+#[test]
+fn fno_c_flags_reach_the_compiler() {
+    // only -fno-<pass> is flakc's own, so a -fno- flag for the C compiler has to be passed on unchanged
+    let dir = temp_dir("fno");
+    let output = flakc(&dir, "({}())", &["--cflag", "-fno-strict-aliasing", "-o", "prog"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let run = Command::new(dir.join("prog")).arg("4").output().unwrap();
+    assert_eq!(run.stdout, b"5\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_exit_with_failure() {
+    let dir = temp_dir("errors");
+    let parse_error = flakc(&dir, "(()", &["-o", "prog"]);
+    assert_eq!(parse_error.status.code(), Some(1));
+    // a warning that --deny turns into an error stops compilation the same way
+    let denied = flakc(&dir, "x (())", &["--deny", "W0001", "-o", "prog"]);
+    assert_eq!(denied.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&denied.stderr).contains("error[W0001]"));
+    assert!(!dir.join("prog").exists());
+    // as do the warnings found after parsing
+    let denied = flakc(&dir, "(())()", &["--deny", "W0005", "-o", "prog"]);
+    assert_eq!(denied.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&denied.stderr).contains("error[W0005]"));
+    assert!(!dir.join("prog").exists());
+    let warned = flakc(&dir, "x (())", &["-o", "prog"]);
+    assert!(warned.status.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn interpreter_options_need_interpret() {
+    let dir = temp_dir("interpreter-options");
+    for args in [&["--step-limit", "5"][..], &["--trace"], &["--dump-both"]] {
+        let output = flakc(&dir, "(())", &[args, &["-o", "prog"]].concat());
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("can only be used with --interpret"), "{:?}", args);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn interpreter_is_unbounded() {
+    let dir = temp_dir("unbounded");
+    for flag in ["--uint64", "--saturating", "--mod256"] {
+        let output = flakc(&dir, "(())", &["--interpret", flag]);
+        assert_eq!(output.status.code(), Some(1), "{}", flag);
+        assert!(output.stdout.is_empty(), "{}", flag);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn interpreter_output_matches_compiled() {
+    let dir = temp_dir("output");
+    for flags in [&[][..], &["--top-only"], &["--csv"], &["--output-format", "json"], &["--separator", ":"], &["--space-separated"],
+        &["--hex", "--hex-prefix"], &["--hex", "--hex-negatives", "twos-complement"]] {
+        for (src, input) in [("({}[()()()])", "1 -1 200"), ("{}", "")] {
+            let compiled = flakc(&dir, src, &[flags, &["-o", "prog"]].concat());
+            assert!(compiled.status.success(), "{:?}", flags);
+            let expected = Command::new(dir.join("prog")).args(input.split_whitespace()).output().unwrap().stdout;
+            let interpreted = flakc(&dir, src, &[flags, &["--interpret", "--args", input]].concat());
+            assert_eq!(String::from_utf8_lossy(&interpreted.stdout), String::from_utf8_lossy(&expected), "{} with {:?}", src, flags);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
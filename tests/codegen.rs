@@ -0,0 +1,118 @@
+use flakc::{ast, compile_to_string, gen, parser};
+use num_bigint::BigInt;
+use std::process::Command;
+
+// the IR of a program after running the given passes, one line per effect
+fn ir(src: &str, passes: &[ast::Pass]) -> String {
+    let mut parser = parser::Parser::new(src, parser::Options::default());
+    let e = ast::translate_with(parser.parse().unwrap(), &mut Vec::new(), passes);
+    let mut b = Vec::new();
+    ast::write_ir(&mut b, &e).unwrap();
+    String::from_utf8(b).unwrap()
+}
+
+// the C for a program after running the given passes
+fn c(src: &str, passes: &[ast::Pass]) -> String {
+    c_with(src, passes, &gen::Options::default())
+}
+
+fn c_with(src: &str, passes: &[ast::Pass], opts: &gen::Options) -> String {
+    let mut parser = parser::Parser::new(src, parser::Options::default());
+    let e = ast::translate_with(parser.parse().unwrap(), &mut Vec::new(), passes);
+    let mut b = Vec::new();
+    gen::compile(&mut b, e, opts).unwrap();
+    String::from_utf8(b).unwrap()
+}
+
+#[test]
+fn copy_reads_once() {
+    // both pushes are the same value, which should be read from the stack once and then written twice
+    assert_eq!(ir("(({}))", &ast::Pass::ALL), "cur: pop 1, push cur[0], cur[0]\n");
+    let c = compile_to_string("(({}))").unwrap();
+    assert_eq!(c.matches("s[p-1]").count(), 1, "{}", c);
+}
+
+#[test]
+fn double_negation_folds_away() {
+    assert_eq!(ir("[[(())]]", &ast::Pass::ALL), ir("(())", &ast::Pass::ALL));
+    assert_eq!(compile_to_string("[[(())]]").unwrap(), compile_to_string("(())").unwrap());
+}
+
+#[test]
+fn double_negation_round_trips() {
+    // without the pass that drops [[a]] from the tree, the value itself is negated twice
+    let passes = [ast::Pass::LoopIdioms, ast::Pass::Hoist];
+    for (src, plain) in [("[[({})]]", "({})"), ("[[({}[()])]]", "({}[()])"), ("[[(({}){}[({})])]]", "(({}){}[({})])")] {
+        assert_eq!(ir(src, &passes), ir(plain, &passes));
+        let code = c(src, &passes);
+        assert_eq!(code, c(plain, &passes));
+        assert!(!code.contains("*-1") && !code.contains("-(-"), "{}", code);
+    }
+}
+
+#[test]
+fn lone_push_is_direct() {
+    // a single push with no pops is written straight to the stack, without a temp
+    let code = compile_to_string("(())").unwrap();
+    assert!(code.contains("s[p]=(1);p++;"), "{}", code);
+    assert!(!code.contains("t0_"), "{}", code);
+}
+
+// compiles a program with gcc and runs it once on each input, returning the final stacks bottom first like the
+// interpreter does
+fn run_compiled(name: &str, src: &str, inputs: &[&[i64]]) -> Vec<Vec<BigInt>> {
+    let dir = std::env::temp_dir().join(format!("flakc-codegen-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("prog.c"), compile_to_string(src).unwrap()).unwrap();
+    let status = Command::new("gcc").current_dir(&dir).args(["prog.c", "-o", "prog"]).status().unwrap();
+    assert!(status.success());
+    let results = inputs.iter().map(|input| {
+        let output = Command::new(dir.join("prog")).args(input.iter().map(i64::to_string)).output().unwrap();
+        assert!(output.status.success());
+        // printed top first
+        String::from_utf8(output.stdout).unwrap().lines().rev().map(|v| v.parse().unwrap()).collect()
+    }).collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+    results
+}
+
+#[test]
+fn size_after_toggles_and_pops() {
+    // [] has to see the pushes and pops still pending on whichever stack is active when it runs, and the pops can
+    // be more than the stack holds
+    let inputs: [&[i64]; 4] = [&[], &[5], &[1, 2], &[4, 3, 2, 1]];
+    for (i, src) in ["<>(())[]", "<>[]", "({}{}[])", "(<>(())<>[])"].into_iter().enumerate() {
+        for (input, output) in inputs.iter().zip(run_compiled(&i.to_string(), src, &inputs)) {
+            let input: Vec<BigInt> = input.iter().map(|&v| BigInt::from(v)).collect();
+            assert_eq!(output, flakc::run_in_memory(src, &input).unwrap(), "{} on {:?}", src, input);
+        }
+    }
+}
+
+#[test]
+fn c89_loop_limits() {
+    // every kind of loop has its own guard, and none of them can use a long long constant
+    let opts = gen::Options { c_standard: gen::CStandard::C89, loop_limit: Some(100), ..gen::Options::default() };
+    let dir = std::env::temp_dir().join(format!("flakc-codegen-c89-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (i, src) in ["{({}<>)<>}", "{({}<>)<>}<>", "{({}[()])}", "{{}}", "({({}[()])}{})", "{({}[()]<>)<>}"].into_iter().enumerate() {
+        let name = format!("prog{}.c", i);
+        std::fs::write(dir.join(&name), c_with(src, &ast::Pass::ALL, &opts)).unwrap();
+        let output = Command::new("gcc").current_dir(&dir).args(["-std=c89", "-pedantic-errors", "-c", &name, "-o", "/dev/null"]).output().unwrap();
+        assert!(output.status.success(), "{}: {}", src, String::from_utf8_lossy(&output.stderr));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reversal_matches_interpreter() {
+    // the whole stack moving onto an empty off stack is done in place, and anything else falls back to moving one
+    // value at a time: a zero stops the move partway, and the second program has a value on the off stack already
+    let inputs: [&[i64]; 5] = [&[], &[5], &[1, 2, 3], &[4, 0, 2, 3], &[0]];
+    for (i, src) in ["{({}<>)<>}<>", "<>(()()())<>{({}<>)<>}<>"].into_iter().enumerate() {
+        for (input, output) in inputs.iter().zip(run_compiled(&format!("reverse{}", i), src, &inputs)) {
+            let input: Vec<BigInt> = input.iter().map(|&v| BigInt::from(v)).collect();
+            assert_eq!(output, flakc::run_in_memory(src, &input).unwrap(), "{} on {:?}", src, input);
+        }
+    }
+}
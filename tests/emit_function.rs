@@ -0,0 +1,48 @@
+use flakc::{ast, gen, parser};
+use num_bigint::BigInt;
+use std::process::Command;
+
+// a program with loops, both stacks and a result that depends on the input, so anything left over from one call
+// would change the next one's result
+const PROGRAM: &str = "({({}[()])}{})<>(()())<>{({}<>)<>}<>";
+
+fn stack(values: &[i64]) -> String {
+    values.iter().map(|v| format!("{}LL", v)).collect::<Vec<_>>().join(",")
+}
+
+#[test]
+fn calls_are_independent() {
+    let mut parser = parser::Parser::new(PROGRAM, parser::Options::default());
+    let code = ast::translate(parser.parse().unwrap(), &mut Vec::new());
+    let opts = gen::Options { function: Some(String::from("bf")), loop_limit: Some(1000), ..gen::Options::default() };
+    let mut c = Vec::new();
+    gen::compile(&mut c, code, &opts).unwrap();
+
+    // the same input is given first and last, so its result must match even after a different call in between
+    let inputs: [&[i64]; 3] = [&[7, 5], &[1, 2, 3], &[7, 5]];
+    let mut driver = String::from("#include<stdio.h>\n#include<stdlib.h>\n");
+    driver.push_str(&format!("{};\nint main(void){{long long*o;size_t m,i;", gen::function_signature("bf", gen::IntType::I64, gen::CStandard::C99)));
+    for input in inputs {
+        driver.push_str(&format!("{{long long a[]={{{}}};bf(a,{},&o,&m);}}", stack(input), input.len()));
+        driver.push_str("for(i=0;i<m;i++)printf(\"%lld \",o[i]);printf(\"\\n\");free(o);");
+    }
+    driver.push_str("return 0;}\n");
+
+    let dir = std::env::temp_dir().join(format!("flakc-emit-function-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("bf.c"), c).unwrap();
+    std::fs::write(dir.join("driver.c"), driver).unwrap();
+    let status = Command::new("gcc").current_dir(&dir).args(["bf.c", "driver.c", "-o", "driver"]).status().unwrap();
+    assert!(status.success());
+    let output = Command::new(dir.join("driver")).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let lines: Vec<String> = String::from_utf8(output.stdout).unwrap().lines().map(String::from).collect();
+    for (input, line) in inputs.iter().zip(&lines) {
+        let input: Vec<BigInt> = input.iter().map(|&v| BigInt::from(v)).collect();
+        let expected = flakc::run_in_memory(PROGRAM, &input).unwrap();
+        let expected: String = expected.iter().map(|v| format!("{} ", v)).collect();
+        assert_eq!(*line, expected);
+    }
+    assert_eq!(lines.len(), inputs.len());
+}
@@ -0,0 +1,15 @@
+use flakc::run_in_memory;
+use num_bigint::BigInt;
+
+#[test]
+fn block_comment_ends() {
+    let one = vec![BigInt::from(1)];
+    // a } that balances the braces ends the comment by itself, so a # straight after it starts a line comment
+    assert_eq!(run_in_memory("#{ c }# (())", &[]).unwrap(), vec![]);
+    assert_eq!(run_in_memory("#{ c }# (())\n(())", &[]).unwrap(), one);
+    assert_eq!(run_in_memory("#{ c } (())", &[]).unwrap(), one);
+    // while the braces are unbalanced, }# ends the comment and takes its # with it
+    assert_eq!(run_in_memory("#{ { c }# (())", &[]).unwrap(), one);
+    assert_eq!(run_in_memory("#{ {{ c }# (())", &[]).unwrap(), one);
+    assert!(run_in_memory("#{ { c } (())", &[]).is_err());
+}